@@ -0,0 +1,119 @@
+//Compares loading a driver's seats with a query per seat against the set based queries the driver info endpoint uses.
+//Run with BENCH_DATABASE_URL pointing at an empty, migrated database, driver_info_seed.sql is loaded into it:
+//cargo bench --bench driver_info
+use std::time::{Duration, Instant};
+
+use itertools::Itertools;
+use sqlx::postgres::PgPoolOptions;
+use sqlx::{Pool, Postgres};
+use tokio::task::JoinSet;
+
+use formula_destruction_backend::models::db_objects::{RaceResult, Seat, Team};
+use formula_destruction_backend::utils::db;
+
+const SEED: &str = include_str!("driver_info_seed.sql");
+const ITERATIONS: u32 = 50;
+
+//How seats used to be loaded: the seat ids, then a results and a team query per seat
+async fn seats_per_seat(pool: &Pool<Postgres>, driver_id: i32) -> Vec<Seat> {
+    let seat_ids = sqlx::query_scalar!("SELECT seat_id FROM drives_in WHERE driver_id = $1", driver_id)
+        .fetch_all(pool)
+        .await
+        .unwrap();
+
+    let mut joinset = JoinSet::new();
+    for seat_id in seat_ids {
+        let pool = pool.clone();
+        joinset.spawn(async move {
+            let results = sqlx::query_as!(
+                RaceResult,
+                r#"
+                SELECT
+                    result.position AS position, bot_result, points.pole AS pole, points.leading_lap AS leading_lap,
+                    points.fastest_lap as fastest_lap, COALESCE(q.position, result.qualy_result) AS qualy_result,
+                    result.season as season, races.race_id as race_id, race_name, races.round as round, points,
+                    race_time, gap_to_winner, laps_completed, best_lap_time, pit_stops
+                FROM result
+                JOIN has_result ON result.result_id = has_result.result_id
+                JOIN races ON result.race_id = races.race_id
+                LEFT JOIN qualifying_result q ON q.race_id = result.race_id AND q.seat_id = has_result.seat_id
+                JOIN points ON result.season = points.season
+                    AND result.position = points.position
+                    AND COALESCE(q.position = 1, result.pole) = points.pole
+                    AND result.leading_lap = points.leading_lap
+                    AND result.fastest_lap = points.fastest_lap
+                    AND races.season = points.season
+                WHERE has_result.seat_id = $1;
+                "#,
+                seat_id
+            )
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+            let team = sqlx::query_as!(
+                Team,
+                "SELECT team_id, name, color FROM team WHERE team_id IN (SELECT team_id FROM drives_for WHERE drives_for.seat_id = $1)",
+                seat_id
+            )
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+            Seat { seat_id, results, team }
+        });
+    }
+    let mut seats = Vec::new();
+    while let Some(seat) = joinset.join_next().await {
+        seats.push(seat.unwrap());
+    }
+    seats
+}
+
+fn summary(seats: &[Seat]) -> Vec<(i32, i32, usize, i32)> {
+    seats
+        .iter()
+        .map(|seat| {
+            let points = seat.results.iter().map(|result| result.points).sum();
+            (seat.seat_id, seat.team.team_id, seat.results.len(), points)
+        })
+        .sorted()
+        .collect()
+}
+
+async fn run(url: &str) {
+    let pool = PgPoolOptions::new().max_connections(10).connect(url).await.unwrap();
+    let has_data = sqlx::query_scalar!(r#"SELECT EXISTS(SELECT 1 FROM driver) AS "has_data!""#)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    if !has_data {
+        sqlx::raw_sql(SEED).execute(&pool).await.unwrap();
+    }
+
+    //Both loaders have to agree before their timings mean anything
+    let seats = db::get_driver_seats(&pool, 1).await.unwrap();
+    assert_eq!(summary(&seats), summary(&seats_per_seat(&pool, 1).await));
+
+    let mut per_seat = Duration::ZERO;
+    let mut set_based = Duration::ZERO;
+    for iteration in 0..ITERATIONS {
+        let driver_id = iteration as i32 % 20 + 1;
+        let started = Instant::now();
+        seats_per_seat(&pool, driver_id).await;
+        per_seat += started.elapsed();
+        let started = Instant::now();
+        db::get_driver_seats(&pool, driver_id).await.unwrap();
+        set_based += started.elapsed();
+    }
+
+    println!("per seat queries: {:?} per driver", per_seat / ITERATIONS);
+    println!("set based queries: {:?} per driver", set_based / ITERATIONS);
+}
+
+fn main() {
+    //cargo test runs bench targets too, there is nothing to measure without a database of its own
+    let Ok(url) = std::env::var("BENCH_DATABASE_URL") else {
+        println!("BENCH_DATABASE_URL is not set, skipping the driver info benchmark");
+        return;
+    };
+    actix_web::rt::System::new().block_on(run(&url));
+}
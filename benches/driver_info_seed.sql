@@ -0,0 +1,50 @@
+-- Dataset for the driver information benchmark, loaded into an empty, migrated database.
+-- 10 seasons of 20 races with 20 drivers, every driver moves to a new seat every 5 races,
+-- so each driver ends up with 40 seats and 200 results.
+
+INSERT INTO seasons (season, season_name, finished, requires_recalc)
+SELECT s, 'Bench season ' || s, true, false
+FROM generate_series(1, 10) s;
+
+INSERT INTO points (season, position, pole, leading_lap, fastest_lap, points)
+SELECT s, p, pole, leading_lap, fastest_lap, GREATEST(0, 21 - p) + pole::INT + fastest_lap::INT
+FROM generate_series(1, 10) s,
+    generate_series(1, 20) p,
+    (VALUES (false), (true)) a(pole),
+    (VALUES (false), (true)) b(leading_lap),
+    (VALUES (false), (true)) c(fastest_lap);
+
+INSERT INTO team (team_id, name, color)
+SELECT t, 'Bench team ' || t, '#000000'
+FROM generate_series(1, 10) t;
+
+INSERT INTO driver (driver_id, username, driver_number, driver_image_url, country)
+SELECT d, 'bench' || d, d, '', 'NL'
+FROM generate_series(1, 20) d;
+
+INSERT INTO races (race_id, race_name, season, round)
+SELECT (s - 1) * 20 + r, 'Bench race ' || r, s, r
+FROM generate_series(1, 10) s, generate_series(1, 20) r;
+
+INSERT INTO drives_in (seat_id, driver_id)
+SELECT (d - 1) * 40 + k, d
+FROM generate_series(1, 20) d, generate_series(1, 40) k;
+
+INSERT INTO drives_for (seat_id, team_id)
+SELECT (d - 1) * 40 + k, (d + k) % 10 + 1
+FROM generate_series(1, 20) d, generate_series(1, 40) k;
+
+INSERT INTO result (result_id, position, bot_result, pole, leading_lap, fastest_lap, qualy_result, season, race_id)
+SELECT ((s - 1) * 20 + r - 1) * 20 + d, (d + r) % 20 + 1, d % 7 = 0 AND r % 3 = 0, (d + r) % 20 = 0,
+    false, (d + r) % 20 = 1, (d + r) % 20 + 1, s, (s - 1) * 20 + r
+FROM generate_series(1, 10) s, generate_series(1, 20) r, generate_series(1, 20) d;
+
+INSERT INTO has_result (result_id, seat_id)
+SELECT ((s - 1) * 20 + r - 1) * 20 + d, (d - 1) * 40 + (s - 1) * 4 + (r - 1) / 5 + 1
+FROM generate_series(1, 10) s, generate_series(1, 20) r, generate_series(1, 20) d;
+
+SELECT setval(pg_get_serial_sequence('team', 'team_id'), 10);
+SELECT setval(pg_get_serial_sequence('driver', 'driver_id'), 20);
+SELECT setval(pg_get_serial_sequence('races', 'race_id'), 200);
+SELECT setval(pg_get_serial_sequence('drives_in', 'seat_id'), 800);
+SELECT setval(pg_get_serial_sequence('result', 'result_id'), 4000);
@@ -0,0 +1,17 @@
+-- Full qualifying classification per race, one row per seat.
+-- `stage` is the last knockout stage the driver took part in (1 = Q1, 2 = Q2, 3 = Q3).
+-- Lap times are stored in milliseconds, `grid_position` is the starting slot after penalties.
+CREATE TABLE qualifying_result
+(
+    qualifying_result_id SERIAL PRIMARY KEY,
+    race_id              INT NOT NULL REFERENCES races (race_id),
+    seat_id              INT NOT NULL REFERENCES drives_in (seat_id),
+    position             INT NOT NULL CHECK (position > 0),
+    stage                INT NOT NULL DEFAULT 1 CHECK (stage BETWEEN 1 AND 3),
+    q1_time              INT CHECK (q1_time > 0),
+    q2_time              INT CHECK (q2_time > 0),
+    q3_time              INT CHECK (q3_time > 0),
+    grid_position        INT CHECK (grid_position > 0),
+    UNIQUE (race_id, seat_id),
+    UNIQUE (race_id, position)
+);
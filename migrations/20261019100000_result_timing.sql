@@ -0,0 +1,8 @@
+-- Timing data for race results, all times are stored in milliseconds.
+-- `gap_to_winner` is only meaningful for cars on the lead lap, lapped cars are derived from `laps_completed`.
+ALTER TABLE result
+    ADD COLUMN race_time      INT CHECK (race_time > 0),
+    ADD COLUMN gap_to_winner  INT CHECK (gap_to_winner >= 0),
+    ADD COLUMN laps_completed INT CHECK (laps_completed >= 0),
+    ADD COLUMN best_lap_time  INT CHECK (best_lap_time > 0),
+    ADD COLUMN pit_stops      INT CHECK (pit_stops >= 0);
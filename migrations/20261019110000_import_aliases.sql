@@ -0,0 +1,8 @@
+-- Manually maintained mapping of in-game names to drivers, used when importing session files.
+CREATE TABLE import_alias
+(
+    alias     TEXT PRIMARY KEY,
+    driver_id INT NOT NULL REFERENCES driver (driver_id)
+);
+
+CREATE UNIQUE INDEX import_alias_lower_idx ON import_alias (lower(alias));
@@ -0,0 +1,14 @@
+-- Usernames a driver raced under before their current one, valid for an inclusive range of seasons.
+-- An open ended range (last_season IS NULL) means the name is still in use.
+CREATE TABLE driver_alias
+(
+    driver_alias_id SERIAL PRIMARY KEY,
+    driver_id       INT  NOT NULL REFERENCES driver (driver_id),
+    username        TEXT NOT NULL,
+    first_season    INT  NOT NULL REFERENCES seasons (season),
+    last_season     INT REFERENCES seasons (season),
+    CHECK (last_season IS NULL OR last_season >= first_season)
+);
+
+CREATE INDEX driver_alias_driver_idx ON driver_alias (driver_id);
+CREATE INDEX driver_alias_username_idx ON driver_alias (lower(username));
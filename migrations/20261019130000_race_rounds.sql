@@ -0,0 +1,12 @@
+-- Round number of a race within its season, existing races are numbered in the order they were created.
+ALTER TABLE races ADD COLUMN round INT;
+
+UPDATE races
+SET round = numbered.round
+FROM (SELECT race_id, row_number() OVER (PARTITION BY season ORDER BY race_id) AS round FROM races) numbered
+WHERE races.race_id = numbered.race_id;
+
+ALTER TABLE races
+    ALTER COLUMN round SET NOT NULL,
+    ADD CONSTRAINT races_round_positive CHECK (round > 0),
+    ADD CONSTRAINT races_season_round_unique UNIQUE (season, round);
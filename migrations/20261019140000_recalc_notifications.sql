@@ -0,0 +1,60 @@
+-- Flags the season of a changed row for recalculation and notifies listening servers.
+CREATE FUNCTION notify_season_change() RETURNS trigger AS
+$$
+DECLARE
+    changed        RECORD;
+    changed_season INT;
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        changed := OLD;
+    ELSE
+        changed := NEW;
+    END IF;
+
+    IF TG_TABLE_NAME = 'qualifying_result' THEN
+        SELECT season INTO changed_season FROM races WHERE race_id = changed.race_id;
+    ELSE
+        changed_season := changed.season;
+    END IF;
+
+    IF changed_season IS NOT NULL THEN
+        UPDATE seasons SET requires_recalc = true WHERE season = changed_season AND NOT requires_recalc;
+        PERFORM pg_notify('season_recalc', changed_season::TEXT);
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER result_season_change
+    AFTER INSERT OR UPDATE OR DELETE
+    ON result
+    FOR EACH ROW
+EXECUTE FUNCTION notify_season_change();
+
+CREATE TRIGGER points_season_change
+    AFTER INSERT OR UPDATE OR DELETE
+    ON points
+    FOR EACH ROW
+EXECUTE FUNCTION notify_season_change();
+
+CREATE TRIGGER qualifying_result_season_change
+    AFTER INSERT OR UPDATE OR DELETE
+    ON qualifying_result
+    FOR EACH ROW
+EXECUTE FUNCTION notify_season_change();
+
+-- Covers seasons being finished or flagged by hand.
+CREATE FUNCTION notify_season_flagged() RETURNS trigger AS
+$$
+BEGIN
+    PERFORM pg_notify('season_recalc', NEW.season::TEXT);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER seasons_flagged
+    AFTER INSERT OR UPDATE OF finished, requires_recalc
+    ON seasons
+    FOR EACH ROW
+    WHEN (NEW.finished AND NEW.requires_recalc)
+EXECUTE FUNCTION notify_season_flagged();
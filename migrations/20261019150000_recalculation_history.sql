@@ -0,0 +1,13 @@
+-- One row per season recalculation attempt, successful or not.
+CREATE TABLE recalculation_history
+(
+    recalculation_id SERIAL PRIMARY KEY,
+    season           INT         NOT NULL REFERENCES seasons (season) ON DELETE CASCADE,
+    started_at       TIMESTAMPTZ NOT NULL,
+    duration_ms      INT         NOT NULL,
+    rows_written     INT         NOT NULL,
+    outcome          TEXT        NOT NULL CHECK (outcome IN ('succeeded', 'failed')),
+    error            TEXT
+);
+
+CREATE INDEX recalculation_history_season_idx ON recalculation_history (season, started_at DESC);
@@ -0,0 +1,11 @@
+-- Driver ratings before and after every race, rebuilt from scratch whenever results change.
+CREATE TABLE rating_history
+(
+    driver_id     INT              NOT NULL REFERENCES driver (driver_id) ON DELETE CASCADE,
+    race_id       INT              NOT NULL REFERENCES races (race_id) ON DELETE CASCADE,
+    rating_before DOUBLE PRECISION NOT NULL,
+    rating_after  DOUBLE PRECISION NOT NULL,
+    PRIMARY KEY (driver_id, race_id)
+);
+
+CREATE INDEX rating_history_race_idx ON rating_history (race_id);
@@ -0,0 +1,3 @@
+-- Marks ratings from races the driver's result was driven by the AI.
+ALTER TABLE rating_history
+    ADD COLUMN bot_result BOOLEAN NOT NULL DEFAULT false;
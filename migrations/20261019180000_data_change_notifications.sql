@@ -0,0 +1,56 @@
+-- Tells listening servers that data behind cached responses changed.
+CREATE FUNCTION notify_data_change() RETURNS trigger AS
+$$
+BEGIN
+    PERFORM pg_notify('data_changed', TG_TABLE_NAME);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER drives_in_data_change
+    AFTER INSERT OR UPDATE OR DELETE OR TRUNCATE
+    ON drives_in
+    FOR EACH STATEMENT
+EXECUTE FUNCTION notify_data_change();
+
+CREATE TRIGGER drives_for_data_change
+    AFTER INSERT OR UPDATE OR DELETE OR TRUNCATE
+    ON drives_for
+    FOR EACH STATEMENT
+EXECUTE FUNCTION notify_data_change();
+
+CREATE TRIGGER driver_data_change
+    AFTER INSERT OR UPDATE OR DELETE OR TRUNCATE
+    ON driver
+    FOR EACH STATEMENT
+EXECUTE FUNCTION notify_data_change();
+
+CREATE TRIGGER team_data_change
+    AFTER INSERT OR UPDATE OR DELETE OR TRUNCATE
+    ON team
+    FOR EACH STATEMENT
+EXECUTE FUNCTION notify_data_change();
+
+CREATE TRIGGER races_data_change
+    AFTER INSERT OR UPDATE OR DELETE OR TRUNCATE
+    ON races
+    FOR EACH STATEMENT
+EXECUTE FUNCTION notify_data_change();
+
+CREATE TRIGGER seasons_data_change
+    AFTER INSERT OR UPDATE OR DELETE OR TRUNCATE
+    ON seasons
+    FOR EACH STATEMENT
+EXECUTE FUNCTION notify_data_change();
+
+CREATE TRIGGER driver_alias_data_change
+    AFTER INSERT OR UPDATE OR DELETE OR TRUNCATE
+    ON driver_alias
+    FOR EACH STATEMENT
+EXECUTE FUNCTION notify_data_change();
+
+CREATE TRIGGER has_result_data_change
+    AFTER INSERT OR UPDATE OR DELETE OR TRUNCATE
+    ON has_result
+    FOR EACH STATEMENT
+EXECUTE FUNCTION notify_data_change();
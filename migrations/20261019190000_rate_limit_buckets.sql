@@ -0,0 +1,7 @@
+-- Token buckets of the shared rate limiter, losing them on a crash only resets the limits
+CREATE UNLOGGED TABLE rate_limit_bucket (
+    key TEXT PRIMARY KEY,
+    tokens DOUBLE PRECISION NOT NULL,
+    allowed BOOLEAN NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL
+);
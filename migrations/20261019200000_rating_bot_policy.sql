@@ -0,0 +1,8 @@
+-- Ratings are computed twice, once from every result and once with the results driven by the AI left out,
+-- so bots=exclude gets ratings that never saw a bot result instead of a filtered view of the other run.
+ALTER TABLE rating_history
+    ADD COLUMN bots_excluded BOOLEAN NOT NULL DEFAULT false;
+
+ALTER TABLE rating_history
+    DROP CONSTRAINT rating_history_pkey,
+    ADD PRIMARY KEY (driver_id, race_id, bots_excluded);
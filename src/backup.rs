@@ -0,0 +1,591 @@
+use std::error::Error;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres, Transaction};
+use tracing::info;
+
+use crate::utils::metrics::Timed;
+
+//Bump whenever the layout of the archive changes, restores refuse archives from a newer version
+pub const ARCHIVE_VERSION: u32 = 2;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Archive {
+    pub version: u32,
+    pub created_at: DateTime<Utc>,
+    pub schema_version: Option<i64>,
+    pub seasons: Vec<SeasonRow>,
+    pub points: Vec<PointsRow>,
+    pub drivers: Vec<DriverRow>,
+    pub teams: Vec<TeamRow>,
+    pub races: Vec<RaceRow>,
+    pub seats: Vec<SeatRow>,
+    pub results: Vec<ResultRow>,
+    pub season_results: Vec<SeasonResultRow>,
+    pub qualifying_results: Vec<QualifyingRow>,
+    pub driver_aliases: Vec<DriverAliasRow>,
+    pub import_aliases: Vec<ImportAliasRow>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SeasonRow {
+    pub season: i32,
+    pub season_name: String,
+    pub finished: bool,
+    pub requires_recalc: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PointsRow {
+    pub season: i32,
+    pub position: i32,
+    pub pole: bool,
+    pub leading_lap: bool,
+    pub fastest_lap: bool,
+    pub points: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DriverRow {
+    pub driver_id: i32,
+    pub username: String,
+    pub driver_number: i32,
+    pub driver_image_url: String,
+    pub country: String,
+    pub birthday: Option<chrono::NaiveDate>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TeamRow {
+    pub team_id: i32,
+    pub name: String,
+    pub color: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RaceRow {
+    pub race_id: i32,
+    pub race_name: String,
+    pub season: i32,
+    //Added in version 2, older archives get their rounds numbered by race id
+    #[serde(default)]
+    pub round: Option<i32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SeatRow {
+    pub seat_id: i32,
+    pub driver_id: i32,
+    pub team_id: Option<i32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ResultRow {
+    pub result_id: i32,
+    pub seat_id: Option<i32>,
+    pub position: i32,
+    pub bot_result: bool,
+    pub pole: bool,
+    pub leading_lap: bool,
+    pub fastest_lap: bool,
+    pub qualy_result: Option<i32>,
+    pub season: i32,
+    pub race_id: i32,
+    pub race_time: Option<i32>,
+    pub gap_to_winner: Option<i32>,
+    pub laps_completed: Option<i32>,
+    pub best_lap_time: Option<i32>,
+    pub pit_stops: Option<i32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SeasonResultRow {
+    pub driver_id: i32,
+    pub driver_result: i32,
+    pub team_result: i32,
+    pub season: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct QualifyingRow {
+    pub qualifying_result_id: i32,
+    pub race_id: i32,
+    pub seat_id: i32,
+    pub position: i32,
+    pub stage: i32,
+    pub q1_time: Option<i32>,
+    pub q2_time: Option<i32>,
+    pub q3_time: Option<i32>,
+    pub grid_position: Option<i32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DriverAliasRow {
+    pub driver_alias_id: i32,
+    pub driver_id: i32,
+    pub username: String,
+    pub first_season: i32,
+    pub last_season: Option<i32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ImportAliasRow {
+    pub alias: String,
+    pub driver_id: i32,
+}
+
+pub async fn create(pool: &Pool<Postgres>) -> Result<Archive, sqlx::Error> {
+    //A repeatable read transaction gives every table the same snapshot
+    let mut tx = pool.begin().await?;
+    sqlx::query!("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ READ ONLY")
+        .execute(&mut *tx)
+        .timed("set_backup_isolation")
+        .await?;
+
+    let schema_version = sqlx::query_scalar!(
+        "SELECT max(version) FROM _sqlx_migrations WHERE success = true"
+    )
+    .fetch_one(&mut *tx)
+    .timed("get_schema_version")
+    .await?;
+
+    let archive = Archive {
+        version: ARCHIVE_VERSION,
+        created_at: Utc::now(),
+        schema_version,
+        seasons: sqlx::query_as!(
+            SeasonRow,
+            "SELECT season, season_name, finished, requires_recalc FROM seasons ORDER BY season"
+        )
+        .fetch_all(&mut *tx)
+        .timed("backup_seasons")
+        .await?,
+        points: sqlx::query_as!(
+            PointsRow,
+            "SELECT season, position, pole, leading_lap, fastest_lap, points FROM points
+            ORDER BY season, position, pole, leading_lap, fastest_lap"
+        )
+        .fetch_all(&mut *tx)
+        .timed("backup_points")
+        .await?,
+        drivers: sqlx::query_as!(
+            DriverRow,
+            "SELECT driver_id, username, driver_number, driver_image_url, country, birthday FROM driver ORDER BY driver_id"
+        )
+        .fetch_all(&mut *tx)
+        .timed("backup_drivers")
+        .await?,
+        teams: sqlx::query_as!(TeamRow, "SELECT team_id, name, color FROM team ORDER BY team_id")
+            .fetch_all(&mut *tx)
+            .timed("backup_teams")
+            .await?,
+        races: sqlx::query_as!(
+            RaceRow,
+            r#"SELECT race_id, race_name, season, round AS "round?" FROM races ORDER BY race_id"#
+        )
+        .fetch_all(&mut *tx)
+        .timed("backup_races")
+        .await?,
+        seats: sqlx::query_as!(
+            SeatRow,
+            r#"SELECT di.seat_id, di.driver_id, df.team_id AS "team_id?"
+            FROM drives_in di LEFT JOIN drives_for df ON di.seat_id = df.seat_id
+            ORDER BY di.seat_id"#
+        )
+        .fetch_all(&mut *tx)
+        .timed("backup_seats")
+        .await?,
+        results: sqlx::query_as!(
+            ResultRow,
+            r#"SELECT result.result_id, hr.seat_id AS "seat_id?", position, bot_result, pole, leading_lap, fastest_lap,
+                qualy_result, season, race_id, race_time, gap_to_winner, laps_completed, best_lap_time, pit_stops
+            FROM result LEFT JOIN has_result hr ON result.result_id = hr.result_id
+            ORDER BY result.result_id"#
+        )
+        .fetch_all(&mut *tx)
+        .timed("backup_results")
+        .await?,
+        season_results: sqlx::query_as!(
+            SeasonResultRow,
+            "SELECT driver_id, driver_result, team_result, season FROM season_result ORDER BY season, driver_result"
+        )
+        .fetch_all(&mut *tx)
+        .timed("backup_season_results")
+        .await?,
+        qualifying_results: sqlx::query_as!(
+            QualifyingRow,
+            "SELECT qualifying_result_id, race_id, seat_id, position, stage, q1_time, q2_time, q3_time, grid_position
+            FROM qualifying_result ORDER BY qualifying_result_id"
+        )
+        .fetch_all(&mut *tx)
+        .timed("backup_qualifying_results")
+        .await?,
+        driver_aliases: sqlx::query_as!(
+            DriverAliasRow,
+            "SELECT driver_alias_id, driver_id, username, first_season, last_season FROM driver_alias ORDER BY driver_alias_id"
+        )
+        .fetch_all(&mut *tx)
+        .timed("backup_driver_aliases")
+        .await?,
+        import_aliases: sqlx::query_as!(
+            ImportAliasRow,
+            "SELECT alias, driver_id FROM import_alias ORDER BY alias"
+        )
+        .fetch_all(&mut *tx)
+        .timed("backup_import_aliases")
+        .await?,
+    };
+
+    tx.commit().await?;
+    Ok(archive)
+}
+
+//Restores an archive into a database that has the schema but no league data, keeping all ids
+pub async fn restore(pool: &Pool<Postgres>, archive: &Archive) -> Result<(), Box<dyn Error>> {
+    if archive.version > ARCHIVE_VERSION {
+        return Err(format!(
+            "Archive version {} is newer than the supported version {}",
+            archive.version, ARCHIVE_VERSION
+        )
+        .into());
+    }
+
+    let mut tx = pool.begin().await?;
+
+    let has_data = sqlx::query_scalar!(
+        r#"SELECT EXISTS(SELECT 1 FROM seasons) OR EXISTS(SELECT 1 FROM driver) OR EXISTS(SELECT 1 FROM team)
+            OR EXISTS(SELECT 1 FROM drives_in) OR EXISTS(SELECT 1 FROM result) AS "has_data!""#
+    )
+    .fetch_one(&mut *tx)
+    .timed("check_restore_target")
+    .await?;
+    if has_data {
+        return Err("Restores can only be done into an empty database".into());
+    }
+
+    let schema_version = sqlx::query_scalar!(
+        "SELECT max(version) FROM _sqlx_migrations WHERE success = true"
+    )
+    .fetch_one(&mut *tx)
+    .timed("get_schema_version")
+    .await?;
+    if schema_version != archive.schema_version {
+        info!(
+            "Restoring archive from schema version {:?} into schema version {:?}",
+            archive.schema_version, schema_version
+        );
+    }
+
+    insert_archive(&mut tx, archive).await?;
+    reset_sequences(&mut tx).await?;
+
+    tx.commit().await?;
+    Ok(())
+}
+
+async fn insert_archive(
+    tx: &mut Transaction<'_, Postgres>,
+    archive: &Archive,
+) -> Result<(), sqlx::Error> {
+    for season in archive.seasons.iter() {
+        sqlx::query!(
+            "INSERT INTO seasons (season, season_name, finished, requires_recalc) VALUES ($1, $2, $3, $4)",
+            season.season,
+            season.season_name,
+            season.finished,
+            season.requires_recalc
+        )
+        .execute(&mut **tx)
+        .timed("restore_season")
+        .await?;
+    }
+
+    for points in archive.points.iter() {
+        sqlx::query!(
+            "INSERT INTO points (season, position, pole, leading_lap, fastest_lap, points) VALUES ($1, $2, $3, $4, $5, $6)",
+            points.season,
+            points.position,
+            points.pole,
+            points.leading_lap,
+            points.fastest_lap,
+            points.points
+        )
+        .execute(&mut **tx)
+        .timed("restore_points")
+        .await?;
+    }
+
+    for driver in archive.drivers.iter() {
+        sqlx::query!(
+            "INSERT INTO driver (driver_id, username, driver_number, driver_image_url, country, birthday)
+            VALUES ($1, $2, $3, $4, $5, $6)",
+            driver.driver_id,
+            driver.username,
+            driver.driver_number,
+            driver.driver_image_url,
+            driver.country,
+            driver.birthday
+        )
+        .execute(&mut **tx)
+        .timed("restore_driver")
+        .await?;
+    }
+
+    for team in archive.teams.iter() {
+        sqlx::query!(
+            "INSERT INTO team (team_id, name, color) VALUES ($1, $2, $3)",
+            team.team_id,
+            team.name,
+            team.color
+        )
+        .execute(&mut **tx)
+        .timed("restore_team")
+        .await?;
+    }
+
+    for race in archive.races.iter() {
+        sqlx::query!(
+            "INSERT INTO races (race_id, race_name, season, round)
+            VALUES ($1, $2, $3, COALESCE($4, (SELECT count(*) + 1 FROM races WHERE season = $3)::INT))",
+            race.race_id,
+            race.race_name,
+            race.season,
+            race.round
+        )
+        .execute(&mut **tx)
+        .timed("restore_race")
+        .await?;
+    }
+
+    for seat in archive.seats.iter() {
+        sqlx::query!(
+            "INSERT INTO drives_in (seat_id, driver_id) VALUES ($1, $2)",
+            seat.seat_id,
+            seat.driver_id
+        )
+        .execute(&mut **tx)
+        .timed("restore_drives_in")
+        .await?;
+        if let Some(team_id) = seat.team_id {
+            sqlx::query!(
+                "INSERT INTO drives_for (seat_id, team_id) VALUES ($1, $2)",
+                seat.seat_id,
+                team_id
+            )
+            .execute(&mut **tx)
+            .timed("restore_drives_for")
+            .await?;
+        }
+    }
+
+    for result in archive.results.iter() {
+        sqlx::query!(
+            "INSERT INTO result (result_id, position, bot_result, pole, leading_lap, fastest_lap, qualy_result, season, race_id,
+                race_time, gap_to_winner, laps_completed, best_lap_time, pit_stops)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)",
+            result.result_id,
+            result.position,
+            result.bot_result,
+            result.pole,
+            result.leading_lap,
+            result.fastest_lap,
+            result.qualy_result,
+            result.season,
+            result.race_id,
+            result.race_time,
+            result.gap_to_winner,
+            result.laps_completed,
+            result.best_lap_time,
+            result.pit_stops
+        )
+        .execute(&mut **tx)
+        .timed("restore_result")
+        .await?;
+        if let Some(seat_id) = result.seat_id {
+            sqlx::query!(
+                "INSERT INTO has_result (result_id, seat_id) VALUES ($1, $2)",
+                result.result_id,
+                seat_id
+            )
+            .execute(&mut **tx)
+            .timed("restore_has_result")
+            .await?;
+        }
+    }
+
+    for season_result in archive.season_results.iter() {
+        sqlx::query!(
+            "INSERT INTO season_result (driver_id, driver_result, team_result, season) VALUES ($1, $2, $3, $4)",
+            season_result.driver_id,
+            season_result.driver_result,
+            season_result.team_result,
+            season_result.season
+        )
+        .execute(&mut **tx)
+        .timed("restore_season_result")
+        .await?;
+    }
+
+    for qualifying in archive.qualifying_results.iter() {
+        sqlx::query!(
+            "INSERT INTO qualifying_result (qualifying_result_id, race_id, seat_id, position, stage, q1_time, q2_time, q3_time, grid_position)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+            qualifying.qualifying_result_id,
+            qualifying.race_id,
+            qualifying.seat_id,
+            qualifying.position,
+            qualifying.stage,
+            qualifying.q1_time,
+            qualifying.q2_time,
+            qualifying.q3_time,
+            qualifying.grid_position
+        )
+        .execute(&mut **tx)
+        .timed("restore_qualifying_result")
+        .await?;
+    }
+
+    for alias in archive.driver_aliases.iter() {
+        sqlx::query!(
+            "INSERT INTO driver_alias (driver_alias_id, driver_id, username, first_season, last_season) VALUES ($1, $2, $3, $4, $5)",
+            alias.driver_alias_id,
+            alias.driver_id,
+            alias.username,
+            alias.first_season,
+            alias.last_season
+        )
+        .execute(&mut **tx)
+        .timed("restore_driver_alias")
+        .await?;
+    }
+
+    for alias in archive.import_aliases.iter() {
+        sqlx::query!(
+            "INSERT INTO import_alias (alias, driver_id) VALUES ($1, $2)",
+            alias.alias,
+            alias.driver_id
+        )
+        .execute(&mut **tx)
+        .timed("restore_import_alias")
+        .await?;
+    }
+
+    //Inserting points, results and qualifying flags their seasons, so the archived flags are put back last
+    for season in archive.seasons.iter() {
+        sqlx::query!(
+            "UPDATE seasons SET requires_recalc = $2 WHERE season = $1 AND requires_recalc <> $2",
+            season.season,
+            season.requires_recalc
+        )
+        .execute(&mut **tx)
+        .timed("restore_requires_recalc")
+        .await?;
+    }
+
+    Ok(())
+}
+
+//Ids were inserted explicitly, so serial sequences have to continue after the restored rows
+async fn reset_sequences(tx: &mut Transaction<'_, Postgres>) -> Result<(), sqlx::Error> {
+    let sequences = [
+        ("driver", "driver_id"),
+        ("team", "team_id"),
+        ("races", "race_id"),
+        ("drives_in", "seat_id"),
+        ("result", "result_id"),
+        ("qualifying_result", "qualifying_result_id"),
+        ("driver_alias", "driver_alias_id"),
+    ];
+
+    for (table, column) in sequences {
+        let query = format!(
+            "SELECT setval(pg_get_serial_sequence('{table}', '{column}'), COALESCE(max({column}), 0) + 1, false) FROM {table}"
+        );
+        sqlx::query(&query).execute(&mut **tx).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::*;
+    use crate::utils::test_db::{empty, seeded_pool};
+
+    //Everything but the time the archive was taken
+    fn contents(archive: &Archive) -> Value {
+        let mut value = serde_json::to_value(archive).unwrap();
+        value.as_object_mut().unwrap().remove("created_at");
+        value
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn restored_backups_match_the_original() {
+        let (_lock, pool) = seeded_pool().await;
+        sqlx::raw_sql(
+            "INSERT INTO driver_alias (driver_id, username, first_season, last_season) VALUES (1, 'OldName', 2, 3);
+            INSERT INTO import_alias (alias, driver_id) VALUES ('b1', 1);
+            INSERT INTO qualifying_result (race_id, seat_id, position, stage, q1_time, grid_position) VALUES (1, 1, 1, 1, 81250, 2);
+            UPDATE seasons SET requires_recalc = (season % 2 = 0);",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let original = create(&pool).await.unwrap();
+        assert_eq!(original.version, ARCHIVE_VERSION);
+        assert!(restore(&pool, &original).await.is_err(), "restored into a database with data");
+
+        empty(&pool).await;
+        restore(&pool, &original).await.unwrap();
+        let restored = create(&pool).await.unwrap();
+        assert_eq!(contents(&restored), contents(&original));
+
+        //Restoring results must not flag the seasons that were up to date
+        let flags: Vec<(i32, bool)> = restored
+            .seasons
+            .iter()
+            .map(|season| (season.season, season.requires_recalc))
+            .collect();
+        assert!(flags.iter().any(|(_, flagged)| !flagged));
+        assert!(flags.iter().all(|(season, flagged)| *flagged == (season % 2 == 0)));
+
+        //Sequences continue after the restored ids
+        let driver_id = sqlx::query_scalar!(
+            "INSERT INTO driver (username, driver_number, driver_image_url, country) VALUES ('new', 99, '', 'NL') RETURNING driver_id"
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(driver_id, 21);
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn version_1_archives_get_their_rounds_numbered() {
+        let (_lock, pool) = seeded_pool().await;
+        let original = create(&pool).await.unwrap();
+
+        //Version 1 had no rounds, races were numbered in the order they were created
+        let mut value = serde_json::to_value(&original).unwrap();
+        value["version"] = Value::from(1);
+        for race in value["races"].as_array_mut().unwrap() {
+            race.as_object_mut().unwrap().remove("round");
+        }
+        let old: Archive = serde_json::from_value(value).unwrap();
+        assert!(old.races.iter().all(|race| race.round.is_none()));
+
+        empty(&pool).await;
+        restore(&pool, &old).await.unwrap();
+        let restored = create(&pool).await.unwrap();
+        assert_eq!(contents(&restored)["races"], contents(&original)["races"]);
+
+        let mut newer = old;
+        newer.version = ARCHIVE_VERSION + 1;
+        empty(&pool).await;
+        assert!(restore(&pool, &newer).await.is_err(), "restored an archive from a newer version");
+    }
+}
@@ -0,0 +1,191 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use clap::{Parser, Subcommand, ValueEnum};
+use sqlx::{Pool, Postgres};
+
+use crate::backup;
+use crate::exporter::{self, Export};
+use crate::importer::{self, ImportPreview, SessionFormat};
+use crate::utils::db;
+use crate::utils::metrics::Timed;
+
+#[derive(Debug, Parser)]
+#[command(version, about = "Formula Destruction backend")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run the HTTP server (default)
+    Serve,
+    /// Import a session file exported by the game into a race
+    Import {
+        /// Race the results belong to
+        #[arg(long)]
+        race: i32,
+        /// File format, derived from the file extension when omitted
+        #[arg(long, value_enum)]
+        format: Option<SessionFormat>,
+        /// Write the results instead of only printing the preview
+        #[arg(long)]
+        confirm: bool,
+        /// Seat for a name from the session file, for drivers whose seat can't be derived. Can be repeated
+        #[arg(long = "seat", value_name = "NAME=SEAT_ID", value_parser = parse_seat)]
+        seats: Vec<(String, i32)>,
+        file: PathBuf,
+    },
+    /// Write the same exports as the /export endpoints to disk
+    Export {
+        /// Only export the results of this season, CSV only
+        #[arg(long)]
+        season: Option<i32>,
+        #[arg(long, value_enum, default_value_t = ExportFormat::Csv)]
+        format: ExportFormat,
+        output: PathBuf,
+    },
+    /// Write a snapshot of all league data to a JSON archive
+    Backup { output: PathBuf },
+    /// Restore a JSON archive into an empty database
+    Restore { file: PathBuf },
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+}
+
+pub async fn import(
+    pool: &Pool<Postgres>,
+    race_id: i32,
+    format: Option<SessionFormat>,
+    confirm: bool,
+    seats: Vec<(String, i32)>,
+    file: &Path,
+) -> Result<(), Box<dyn Error>> {
+    let format = match format {
+        Some(format) => format,
+        None => match file.extension().and_then(|e| e.to_str()) {
+            Some(e) if e.eq_ignore_ascii_case("json") => SessionFormat::Json,
+            Some(e) if e.eq_ignore_ascii_case("csv") => SessionFormat::Csv,
+            _ => return Err("Could not derive the file format, pass --format".into()),
+        },
+    };
+
+    let data = std::fs::read(file)?;
+    let entries = importer::parse(format, &data)?;
+    let race = db::get_race(pool, race_id).timed("get_race").await?;
+    let seats: HashMap<String, i32> = seats.into_iter().collect();
+    let preview = importer::build_preview(pool, race, entries, &seats).await?;
+    print_preview(&preview);
+
+    if !preview.is_complete() {
+        return Err("Session file could not be fully mapped".into());
+    }
+    if confirm {
+        let imported = importer::commit(pool, &preview).await?;
+        println!("Imported {imported} results");
+    } else {
+        println!("Run again with --confirm to write these results");
+    }
+    Ok(())
+}
+
+fn print_preview(preview: &ImportPreview) {
+    println!("{} (season {})", preview.race.race_name, preview.race.season);
+    for row in preview.rows.iter() {
+        let driver = row.driver.as_ref().map_or("-", |d| d.username.as_str());
+        let team = row.team.as_ref().map_or("-", |t| t.name.as_str());
+        println!(
+            "{:>4}  {:<24} -> {:<24} {:<24} {}",
+            row.position.to_string(),
+            row.game_name,
+            driver,
+            team,
+            row.matched_by
+                .map_or("unmatched", |m| match m {
+                    importer::MatchedBy::Username => "username",
+                    importer::MatchedBy::Alias => "alias",
+                    importer::MatchedBy::FormerName => "former name",
+                    importer::MatchedBy::Seat => "seat",
+                })
+        );
+    }
+    for problem in preview.problems.iter() {
+        println!("! {problem}");
+    }
+    for warning in preview.warnings.iter() {
+        println!("? {warning}");
+    }
+}
+
+//The name may contain '=' itself, the seat id is whatever follows the last one
+fn parse_seat(value: &str) -> Result<(String, i32), String> {
+    let (name, seat_id) = value
+        .rsplit_once('=')
+        .ok_or_else(|| format!("expected NAME=SEAT_ID, got '{value}'"))?;
+    let seat_id = seat_id
+        .trim()
+        .parse()
+        .map_err(|_| format!("'{seat_id}' is not a seat id"))?;
+    Ok((name.to_string(), seat_id))
+}
+
+pub async fn export(
+    pool: &Pool<Postgres>,
+    season: Option<i32>,
+    format: ExportFormat,
+    output: &Path,
+) -> Result<(), Box<dyn Error>> {
+    let export = match (format, season) {
+        (ExportFormat::Csv, Some(season)) => Export::SeasonCsv(season),
+        (ExportFormat::Csv, None) => Export::ResultsCsv,
+        (ExportFormat::Json, None) => Export::LeagueJson,
+        (ExportFormat::Json, Some(_)) => return Err("The JSON export always contains the full league".into()),
+    };
+
+    let mut file = std::io::BufWriter::new(std::fs::File::create(output)?);
+    let mut chunks = exporter::spawn(pool.clone(), export);
+    while let Some(chunk) = chunks.recv().await {
+        file.write_all(&chunk?)?;
+    }
+    file.flush()?;
+
+    println!("Wrote export to {}", output.display());
+    Ok(())
+}
+
+pub async fn backup(pool: &Pool<Postgres>, output: &Path) -> Result<(), Box<dyn Error>> {
+    let archive = backup::create(pool).await?;
+    let file = std::io::BufWriter::new(std::fs::File::create(output)?);
+    serde_json::to_writer(file, &archive)?;
+
+    println!(
+        "Wrote {} seasons, {} drivers and {} results to {}",
+        archive.seasons.len(),
+        archive.drivers.len(),
+        archive.results.len(),
+        output.display()
+    );
+    Ok(())
+}
+
+pub async fn restore(pool: &Pool<Postgres>, file: &Path) -> Result<(), Box<dyn Error>> {
+    let reader = std::io::BufReader::new(std::fs::File::open(file)?);
+    let archive: backup::Archive = serde_json::from_reader(reader)?;
+    backup::restore(pool, &archive).await?;
+
+    println!(
+        "Restored {} seasons, {} drivers and {} results from {}",
+        archive.seasons.len(),
+        archive.drivers.len(),
+        archive.results.len(),
+        file.display()
+    );
+    Ok(())
+}
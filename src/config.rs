@@ -0,0 +1,342 @@
+use std::str::FromStr;
+use std::time::Duration;
+
+use actix_web::http::uri::Authority;
+use tracing::warn;
+
+//Runtime settings, read from the environment (or .env) with defaults for everything
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub recalc: RecalcConfig,
+    pub rating: RatingConfig,
+    pub cache: CacheConfig,
+    pub shutdown: ShutdownConfig,
+    pub http: HttpConfig,
+    pub rate_limit: RateLimitConfig,
+}
+
+#[derive(Debug, Clone)]
+pub struct RecalcConfig {
+    //Quiet period after the last change before a recalculation starts
+    pub debounce: Duration,
+    //Upper bound on how long a stream of changes can postpone a recalculation
+    pub max_delay: Duration,
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct RatingConfig {
+    pub initial: f64,
+    pub k_factor: f64,
+    //How much a comparison against a result driven by the AI counts, 0 leaves those results out
+    pub bot_weight: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct CacheConfig {
+    pub enabled: bool,
+    //The cache is emptied when it grows past this many responses
+    pub max_entries: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct HttpConfig {
+    //Origins the frontend may call the API from, "*" allows any. CORS is off when there are none
+    pub cors_origins: Vec<String>,
+    pub cors_max_age: usize,
+    pub compression: bool,
+    //Only sent when set, TLS is terminated in front of the server
+    pub hsts_max_age: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateLimitBackend {
+    //Per server, every instance behind a load balancer hands out the full limit
+    Memory,
+    //Buckets in the database, shared by every instance using it
+    Postgres,
+}
+
+impl FromStr for RateLimitBackend {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "memory" => Ok(RateLimitBackend::Memory),
+            "postgres" => Ok(RateLimitBackend::Postgres),
+            _ => Err(format!("unknown rate limit backend '{value}'")),
+        }
+    }
+}
+
+//A token bucket, holding up to burst requests and refilled at per_minute. A per_minute of 0 turns the limit off
+#[derive(Debug, Clone, Copy)]
+pub struct RouteLimit {
+    pub burst: u32,
+    pub per_minute: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct RateLimitConfig {
+    pub enabled: bool,
+    pub backend: RateLimitBackend,
+    //Proxies in front of the server that add to Forwarded or X-Forwarded-For. The client address is taken that many
+    //entries from the right, the ones further left are whatever the client sent. 0 uses the connection's address
+    pub trusted_proxies: usize,
+    pub driver: RouteLimit,
+    pub season: RouteLimit,
+    pub team: RouteLimit,
+    pub race: RouteLimit,
+    pub export: RouteLimit,
+    pub admin: RouteLimit,
+}
+
+#[derive(Debug, Clone)]
+pub struct ShutdownConfig {
+    //How long in-flight requests get to finish once the server stops accepting connections
+    pub requests: Duration,
+    //How long a running recalculation gets to finish before it is aborted and rolled back
+    pub worker: Duration,
+    pub pool: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    //One JSON object per line, with the fields of every enclosing span
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("unknown log format '{value}'")),
+        }
+    }
+}
+
+//Read before everything else, so problems with the rest of the configuration are logged in the right format
+#[derive(Debug, Clone)]
+pub struct LoggingConfig {
+    pub format: LogFormat,
+    pub level: tracing::Level,
+    //Invalid values found while reading, only logged once the subscriber is installed
+    pub warnings: Vec<String>,
+}
+
+impl LoggingConfig {
+    pub fn from_env() -> Self {
+        let default_level = if cfg!(debug_assertions) {
+            tracing::Level::DEBUG
+        } else {
+            tracing::Level::INFO
+        };
+        let mut warnings = Vec::new();
+        LoggingConfig {
+            format: env_parsed("LOG_FORMAT", LogFormat::Text, &mut warnings),
+            level: env_parsed("LOG_LEVEL", default_level, &mut warnings),
+            warnings,
+        }
+    }
+}
+
+//Span export to an OpenTelemetry collector, disabled unless an endpoint is configured
+#[derive(Debug, Clone)]
+pub struct TelemetryConfig {
+    pub endpoint: Option<String>,
+    pub service_name: String,
+    //Share of new traces that is exported, traces continued from an incoming traceparent follow the caller
+    pub sample_ratio: f64,
+    //Invalid values found while reading, only logged once the subscriber is installed
+    pub warnings: Vec<String>,
+}
+
+impl TelemetryConfig {
+    pub fn from_env() -> Self {
+        let mut warnings = Vec::new();
+        TelemetryConfig {
+            endpoint: std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT")
+                .ok()
+                .filter(|endpoint| !endpoint.is_empty()),
+            service_name: env_parsed(
+                "OTEL_SERVICE_NAME",
+                env!("CARGO_PKG_NAME").to_string(),
+                &mut warnings,
+            ),
+            sample_ratio: env_parsed("OTEL_TRACES_SAMPLER_ARG", 1.0_f64, &mut warnings)
+                .clamp(0.0, 1.0),
+            warnings,
+        }
+    }
+}
+
+impl Config {
+    pub fn from_env() -> Self {
+        Config {
+            recalc: RecalcConfig {
+                debounce: env_millis("RECALC_DEBOUNCE_MS", 2_000),
+                max_delay: env_millis("RECALC_MAX_DELAY_MS", 30_000),
+                max_attempts: env_or("RECALC_MAX_ATTEMPTS", 5),
+                initial_backoff: env_millis("RECALC_INITIAL_BACKOFF_MS", 1_000),
+                max_backoff: env_millis("RECALC_MAX_BACKOFF_MS", 60_000),
+            },
+            rating: RatingConfig {
+                initial: env_or("RATING_INITIAL", 1500.0),
+                k_factor: env_or("RATING_K_FACTOR", 32.0),
+                bot_weight: env_or("RATING_BOT_WEIGHT", 0.0_f64).clamp(0.0, 1.0),
+            },
+            cache: CacheConfig {
+                enabled: env_or("CACHE_ENABLED", true),
+                max_entries: env_or("CACHE_MAX_ENTRIES", 1_024),
+            },
+            shutdown: ShutdownConfig {
+                requests: env_secs("SHUTDOWN_REQUEST_TIMEOUT_SECS", 30),
+                worker: env_secs("SHUTDOWN_WORKER_TIMEOUT_SECS", 30),
+                pool: env_secs("SHUTDOWN_POOL_TIMEOUT_SECS", 5),
+            },
+            http: HttpConfig {
+                cors_origins: env_origins("CORS_ALLOWED_ORIGINS"),
+                cors_max_age: env_or("CORS_MAX_AGE_SECS", 3_600),
+                compression: env_or("COMPRESSION_ENABLED", true),
+                hsts_max_age: env_optional("HSTS_MAX_AGE_SECS"),
+            },
+            rate_limit: RateLimitConfig {
+                enabled: env_or("RATE_LIMIT_ENABLED", true),
+                backend: env_or("RATE_LIMIT_BACKEND", RateLimitBackend::Memory),
+                trusted_proxies: env_or("RATE_LIMIT_TRUSTED_PROXIES", 0),
+                driver: env_route_limit("DRIVER", 60, 120),
+                season: env_route_limit("SEASON", 60, 120),
+                team: env_route_limit("TEAM", 60, 120),
+                race: env_route_limit("RACE", 60, 120),
+                //A whole season per request
+                export: env_route_limit("EXPORT", 5, 10),
+                admin: env_route_limit("ADMIN", 10, 30),
+            },
+        }
+    }
+}
+
+fn env_or<T: FromStr>(key: &str, default: T) -> T {
+    let mut warnings = Vec::new();
+    let value = env_parsed(key, default, &mut warnings);
+    for warning in warnings {
+        warn!("{}", warning);
+    }
+    value
+}
+
+//Like env_or, but collects the warning for callers that run before logging is set up
+fn env_parsed<T: FromStr>(key: &str, default: T, warnings: &mut Vec<String>) -> T {
+    match std::env::var(key) {
+        Ok(value) => value.parse().unwrap_or_else(|_| {
+            warnings.push(format!("Invalid value '{}' for {}, using the default", value, key));
+            default
+        }),
+        Err(_) => default,
+    }
+}
+
+fn env_optional<T: FromStr>(key: &str) -> Option<T> {
+    let value = std::env::var(key).ok()?;
+    let parsed = value.parse().ok();
+    if parsed.is_none() {
+        warn!("Invalid value '{}' for {}, ignoring it", value, key);
+    }
+    parsed
+}
+
+//Comma separated, empty entries are skipped
+fn env_list(key: &str) -> Vec<String> {
+    std::env::var(key)
+        .map(|value| {
+            value
+                .split(',')
+                .map(str::trim)
+                .filter(|entry| !entry.is_empty())
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+//An origin as browsers send it: scheme, host and optional port, without a path or a trailing slash
+fn is_origin(origin: &str) -> bool {
+    let Some((scheme, authority)) = origin.split_once("://") else {
+        return false;
+    };
+    matches!(scheme, "http" | "https")
+        && !authority.contains(['/', '?', '#', '@'])
+        && authority.parse::<Authority>().is_ok()
+}
+
+//CORS_ALLOWED_ORIGINS, origins that could never match a request are left out
+fn env_origins(key: &str) -> Vec<String> {
+    env_list(key)
+        .into_iter()
+        .filter(|origin| {
+            let valid = origin == "*" || is_origin(origin);
+            if !valid {
+                warn!("Invalid origin '{}' in {}, ignoring it", origin, key);
+            }
+            valid
+        })
+        .collect()
+}
+
+fn env_millis(key: &str, default: u64) -> Duration {
+    Duration::from_millis(env_or(key, default))
+}
+
+fn env_secs(key: &str, default: u64) -> Duration {
+    Duration::from_secs(env_or(key, default))
+}
+
+//RATE_LIMIT_<GROUP>_BURST and RATE_LIMIT_<GROUP>_PER_MINUTE
+fn env_route_limit(group: &str, burst: u32, per_minute: u32) -> RouteLimit {
+    RouteLimit {
+        burst: env_or(&format!("RATE_LIMIT_{group}_BURST"), burst).max(1),
+        per_minute: env_or(&format!("RATE_LIMIT_{group}_PER_MINUTE"), per_minute),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn origins_need_a_scheme_and_nothing_after_the_host() {
+        assert!(is_origin("https://example.com"));
+        assert!(is_origin("http://localhost:5173"));
+        assert!(is_origin("http://[::1]:8080"));
+
+        assert!(!is_origin("example.com"));
+        assert!(!is_origin("ftp://example.com"));
+        assert!(!is_origin("https://example.com/"));
+        assert!(!is_origin("https://example.com/app"));
+        assert!(!is_origin("https://user@example.com"));
+        assert!(!is_origin("https://"));
+        assert!(!is_origin("https://exa mple.com"));
+    }
+
+    #[test]
+    fn invalid_values_are_collected_instead_of_logged() {
+        //Unique to this test, so it doesn't race with other readers of the environment
+        std::env::set_var("CONFIG_TEST_INVALID_RATIO", "half");
+        std::env::set_var("CONFIG_TEST_VALID_RATIO", "0.5");
+
+        let mut warnings = Vec::new();
+        assert_eq!(env_parsed("CONFIG_TEST_INVALID_RATIO", 1.0_f64, &mut warnings), 1.0);
+        assert_eq!(env_parsed("CONFIG_TEST_VALID_RATIO", 1.0_f64, &mut warnings), 0.5);
+        assert_eq!(env_parsed("CONFIG_TEST_UNSET_RATIO", 1.0_f64, &mut warnings), 1.0);
+        assert_eq!(
+            warnings,
+            ["Invalid value 'half' for CONFIG_TEST_INVALID_RATIO, using the default"]
+        );
+    }
+}
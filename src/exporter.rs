@@ -0,0 +1,325 @@
+use actix_web::web::Bytes;
+use futures::{Stream, TryStreamExt};
+use serde::Serialize;
+use sqlx::{Pool, Postgres};
+use tokio::sync::mpsc;
+use tracing::warn;
+
+use crate::models::db_objects::{DriverInfo, ExportResult, RaceInfo, Season, Team};
+use crate::utils::metrics::Timed;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Export {
+    SeasonCsv(i32),
+    ResultsCsv,
+    LeagueJson,
+}
+
+#[derive(Debug)]
+pub enum ExportError {
+    Database(sqlx::Error),
+    Encoding(String),
+}
+
+impl std::fmt::Display for ExportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExportError::Database(e) => write!(f, "database error: {e}"),
+            ExportError::Encoding(e) => write!(f, "encoding error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for ExportError {}
+
+impl From<sqlx::Error> for ExportError {
+    fn from(value: sqlx::Error) -> Self {
+        ExportError::Database(value)
+    }
+}
+
+type Chunks = mpsc::Sender<Result<Bytes, ExportError>>;
+
+//Runs the export on its own task, chunks are produced as rows arrive so whole histories never sit in memory
+pub fn spawn(pool: Pool<Postgres>, export: Export) -> mpsc::Receiver<Result<Bytes, ExportError>> {
+    let (tx, rx) = mpsc::channel(64);
+    tokio::spawn(async move {
+        let outcome = match export {
+            Export::SeasonCsv(season) => {
+                write_csv(&tx, results(&pool, Some(season)))
+                    .timed("export_results")
+                    .await
+            }
+            Export::ResultsCsv => write_csv(&tx, results(&pool, None)).timed("export_results").await,
+            Export::LeagueJson => write_json(&pool, &tx).await,
+        };
+        if let Err(e) = outcome {
+            warn!("Export {:?} failed: {}", export, e);
+            let _ = tx.send(Err(e)).await;
+        }
+    });
+    rx
+}
+
+//Streamed, so the export_results timing covers sending the rows on as well as the query
+fn results(
+    pool: &Pool<Postgres>,
+    season: Option<i32>,
+) -> impl Stream<Item = Result<ExportResult, sqlx::Error>> + '_ {
+    sqlx::query_as!(
+        ExportResult,
+        r#"
+        SELECT
+            result.result_id,
+            result.season,
+            s.season_name,
+            r.race_id,
+            r.race_name,
+            r.round,
+            d.driver_id,
+            d.username,
+            COALESCE(da.username, d.username) AS "raced_as!",
+            t.team_id,
+            t.name AS team_name,
+            result.position,
+            p.points,
+            result.bot_result,
+            p.pole,
+            result.leading_lap,
+            result.fastest_lap,
+            COALESCE(q.position, result.qualy_result) AS qualy_result,
+            result.race_time,
+            result.gap_to_winner,
+            result.laps_completed,
+            result.best_lap_time,
+            result.pit_stops
+        FROM result
+        JOIN seasons s ON result.season = s.season
+        JOIN races r ON result.race_id = r.race_id
+        JOIN has_result hr ON result.result_id = hr.result_id
+        JOIN drives_in di ON hr.seat_id = di.seat_id
+        JOIN driver d ON di.driver_id = d.driver_id
+        JOIN drives_for df ON hr.seat_id = df.seat_id
+        JOIN team t ON df.team_id = t.team_id
+        LEFT JOIN qualifying_result q ON q.race_id = result.race_id AND q.seat_id = hr.seat_id
+        JOIN points p ON result.season = p.season AND result.position = p.position
+            AND COALESCE(q.position = 1, result.pole) = p.pole
+            AND result.leading_lap = p.leading_lap AND result.fastest_lap = p.fastest_lap
+        LEFT JOIN LATERAL (
+            SELECT username FROM driver_alias
+            WHERE driver_alias.driver_id = d.driver_id
+                AND result.season BETWEEN first_season AND COALESCE(last_season, result.season)
+            ORDER BY first_season DESC LIMIT 1
+        ) da ON true
+        WHERE $1::INT IS NULL OR result.season = $1
+        ORDER BY result.season, r.round, result.position;
+        "#,
+        season
+    )
+    .fetch(pool)
+}
+
+async fn send(tx: &Chunks, chunk: Vec<u8>) -> Result<(), ExportError> {
+    tx.send(Ok(Bytes::from(chunk)))
+        .await
+        .map_err(|_| ExportError::Encoding("export receiver was dropped".into()))
+}
+
+async fn write_csv(
+    tx: &Chunks,
+    rows: impl Stream<Item = Result<ExportResult, sqlx::Error>>,
+) -> Result<(), ExportError> {
+    //Written up front, so a season without results still gets its header
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer
+        .write_record(ExportResult::FIELDS)
+        .map_err(|e| ExportError::Encoding(e.to_string()))?;
+    let chunk = writer
+        .into_inner()
+        .map_err(|e| ExportError::Encoding(e.to_string()))?;
+    send(tx, chunk).await?;
+
+    let mut rows = std::pin::pin!(rows);
+    while let Some(row) = rows.try_next().await? {
+        let mut writer = csv::WriterBuilder::new()
+            .has_headers(false)
+            .from_writer(Vec::new());
+        writer
+            .serialize(&row)
+            .map_err(|e| ExportError::Encoding(e.to_string()))?;
+        let chunk = writer
+            .into_inner()
+            .map_err(|e| ExportError::Encoding(e.to_string()))?;
+        send(tx, chunk).await?;
+    }
+    Ok(())
+}
+
+fn json<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, ExportError> {
+    serde_json::to_vec(value).map_err(|e| ExportError::Encoding(e.to_string()))
+}
+
+async fn write_json(pool: &Pool<Postgres>, tx: &Chunks) -> Result<(), ExportError> {
+    let seasons = sqlx::query_as!(Season, "SELECT season, season_name FROM seasons ORDER BY season")
+        .fetch_all(pool)
+        .timed("export_seasons")
+        .await?;
+    let drivers = sqlx::query_as!(
+        DriverInfo,
+        "SELECT driver_id, username, driver_number, driver_image_url, country, birthday FROM driver ORDER BY driver_id"
+    )
+    .fetch_all(pool)
+    .timed("export_drivers")
+    .await?;
+    let teams = sqlx::query_as!(Team, "SELECT team_id, name, color FROM team ORDER BY team_id")
+        .fetch_all(pool)
+        .timed("export_teams")
+        .await?;
+    let races = sqlx::query_as!(
+        RaceInfo,
+        "SELECT race_name, season, race_id, round FROM races ORDER BY season, round"
+    )
+    .fetch_all(pool)
+    .timed("export_races")
+    .await?;
+
+    let mut head = b"{\"seasons\":".to_vec();
+    head.extend(json(&seasons)?);
+    head.extend(b",\"drivers\":");
+    head.extend(json(&drivers)?);
+    head.extend(b",\"teams\":");
+    head.extend(json(&teams)?);
+    head.extend(b",\"races\":");
+    head.extend(json(&races)?);
+    head.extend(b",\"results\":[");
+    send(tx, head).await?;
+
+    write_json_results(tx, results(pool, None))
+        .timed("export_results")
+        .await?;
+    send(tx, b"]}".to_vec()).await
+}
+
+async fn write_json_results(
+    tx: &Chunks,
+    rows: impl Stream<Item = Result<ExportResult, sqlx::Error>>,
+) -> Result<(), ExportError> {
+    let mut rows = std::pin::pin!(rows);
+    let mut first = true;
+    while let Some(row) = rows.try_next().await? {
+        let mut chunk = if first { Vec::new() } else { b",".to_vec() };
+        chunk.extend(json(&row)?);
+        send(tx, chunk).await?;
+        first = false;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::db_objects::Position;
+
+    use super::*;
+
+    fn row(result_id: i32, username: &str, position: Position) -> ExportResult {
+        ExportResult {
+            result_id,
+            season: 1,
+            season_name: "Season 1".to_string(),
+            race_id: 1,
+            race_name: "Grand Prix, \"Monza\"".to_string(),
+            round: 1,
+            driver_id: result_id,
+            username: username.to_string(),
+            raced_as: username.to_string(),
+            team_id: 1,
+            team_name: "Scuderia\nRosso".to_string(),
+            position,
+            points: 25,
+            bot_result: false,
+            pole: true,
+            leading_lap: false,
+            fastest_lap: false,
+            qualy_result: Some(1),
+            race_time: None,
+            gap_to_winner: None,
+            laps_completed: Some(53),
+            best_lap_time: Some(81_250),
+            pit_stops: None,
+        }
+    }
+
+    fn rows() -> impl Stream<Item = Result<ExportResult, sqlx::Error>> {
+        futures::stream::iter(vec![
+            Ok(row(1, "Jörg, \"the rocket\"", Position::Finished(1))),
+            Ok(row(2, "smith", Position::Dnf)),
+        ])
+    }
+
+    async fn collect(mut rx: mpsc::Receiver<Result<Bytes, ExportError>>) -> String {
+        let mut output = Vec::new();
+        while let Some(chunk) = rx.recv().await {
+            output.extend_from_slice(&chunk.unwrap());
+        }
+        String::from_utf8(output).unwrap()
+    }
+
+    #[actix_web::test]
+    async fn csv_has_one_header_and_quotes_fields() {
+        let (tx, rx) = mpsc::channel(64);
+        write_csv(&tx, rows()).await.unwrap();
+        drop(tx);
+        let output = collect(rx).await;
+
+        assert_eq!(output.matches("result_id,").count(), 1);
+        let mut reader = csv::Reader::from_reader(output.as_bytes());
+        let headers = reader.headers().unwrap().clone();
+        assert_eq!(headers.get(0), Some("result_id"));
+        let records: Vec<csv::StringRecord> = reader.records().map(Result::unwrap).collect();
+        assert_eq!(records.len(), 2);
+
+        let field = |record: &csv::StringRecord, name: &str| {
+            let index = headers.iter().position(|header| header == name).unwrap();
+            record.get(index).unwrap().to_string()
+        };
+        assert_eq!(field(&records[0], "username"), "Jörg, \"the rocket\"");
+        assert_eq!(field(&records[0], "race_name"), "Grand Prix, \"Monza\"");
+        assert_eq!(field(&records[0], "team_name"), "Scuderia\nRosso");
+        assert_eq!(field(&records[0], "position"), "P1");
+        assert_eq!(field(&records[1], "position"), "DNF");
+        assert_eq!(field(&records[0], "race_time"), "");
+        assert_eq!(field(&records[0], "best_lap_time"), "81250");
+
+        //The fixed header has to match the columns serde writes for each row
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        writer.serialize(row(1, "smith", Position::Finished(1))).unwrap();
+        let derived = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        assert_eq!(derived.lines().next(), Some(ExportResult::FIELDS.join(",").as_str()));
+    }
+
+    #[actix_web::test]
+    async fn csv_without_results_still_has_a_header() {
+        let (tx, rx) = mpsc::channel(64);
+        write_csv(&tx, futures::stream::empty()).await.unwrap();
+        drop(tx);
+        let output = collect(rx).await;
+
+        assert_eq!(output, format!("{}\n", ExportResult::FIELDS.join(",")));
+    }
+
+    #[actix_web::test]
+    async fn json_results_form_an_array() {
+        let (tx, rx) = mpsc::channel(64);
+        write_json_results(&tx, rows()).await.unwrap();
+        drop(tx);
+        let output = format!("[{}]", collect(rx).await);
+
+        let results: Vec<serde_json::Value> = serde_json::from_str(&output).unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0]["username"], "Jörg, \"the rocket\"");
+        assert_eq!(results[0]["team_name"], "Scuderia\nRosso");
+        assert_eq!(results[0]["position"], "P1");
+        assert_eq!(results[1]["position"], "DNF");
+        assert!(results[0]["race_time"].is_null());
+    }
+}
@@ -0,0 +1,72 @@
+use actix_web::web;
+use serde::Deserialize;
+use sqlx::{Pool, Postgres};
+use tracing::{info, warn};
+
+use crate::models::api_response::ApiResponse;
+use crate::models::db_objects::RecalculationRun;
+use crate::utils::db;
+use crate::utils::metrics::Timed;
+use crate::utils::recalculation::{RecalcHandle, Trigger};
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::resource("/recalculate/{season}").post(recalculate_season));
+    cfg.service(web::resource("/recalculations").get(get_recalculations));
+}
+
+//Flags a finished season for recalculation and wakes the worker, the rebuild itself runs in the background
+async fn recalculate_season(
+    pool: web::Data<Pool<Postgres>>,
+    recalc: web::Data<RecalcHandle>,
+    season: web::Path<i32>,
+) -> ApiResponse<()> {
+    let pool = pool.get_ref();
+    let season = season.into_inner();
+
+    let flagged = sqlx::query_scalar!(
+        "UPDATE seasons SET requires_recalc = true WHERE season = $1 RETURNING finished",
+        season
+    )
+    .fetch_optional(pool)
+    .timed("flag_season_recalculation")
+    .await;
+
+    match flagged {
+        Ok(Some(true)) => {
+            info!(season, "Forced recalculation requested");
+            recalc.request(Trigger::Season(season));
+            ApiResponse::new_accepted("Recalculation queued")
+        }
+        Ok(Some(false)) => ApiResponse::new_bad_request("Season is not finished yet"),
+        Ok(None) => ApiResponse::new_not_found_error("Season not found"),
+        Err(e) => {
+            warn!("Failed to flag season for recalculation: {:?}", e);
+            ApiResponse::new_internal_error("Failed to queue recalculation")
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct HistoryQuery {
+    season: Option<i32>,
+    limit: Option<i64>,
+}
+
+//Most recent recalculation attempts first, optionally for a single season
+async fn get_recalculations(
+    pool: web::Data<Pool<Postgres>>,
+    query: web::Query<HistoryQuery>,
+) -> ApiResponse<Vec<RecalculationRun>> {
+    let limit = query.limit.unwrap_or(50).clamp(1, 500);
+
+    match db::get_recalculation_history(pool.get_ref(), query.season, limit)
+        .timed("get_recalculation_history")
+        .await
+    {
+        Ok(runs) => ApiResponse::new_ok("Successfully fetched recalculation history", runs),
+        Err(e) => {
+            warn!("Failed to fetch recalculation history: {:?}", e);
+            ApiResponse::new_internal_error("Failed to fetch recalculation history")
+        }
+    }
+}
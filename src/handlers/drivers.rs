@@ -1,16 +1,21 @@
 use actix_web::web;
 use sqlx::{Pool, Postgres};
-use tokio::task::{JoinHandle, JoinSet};
 use tracing::{info, warn};
 
 use crate::models::api_response::ApiResponse;
 use crate::models::db_objects::*;
-use crate::utils::db;
+use crate::utils::metrics::Timed;
+use crate::utils::{db, teammates};
 
 pub fn config(cfg: &mut web::ServiceConfig) {
     cfg.service(web::resource("/test").to(test));
     cfg.service(web::resource("/all_drivers").get(get_all_drivers));
+    cfg.service(web::resource("/search").get(search_drivers));
+    cfg.service(web::resource("/ratings").get(get_driver_ratings));
+    cfg.service(web::resource("/{driver_id}/aliases").get(get_driver_aliases));
     cfg.service(web::resource("/{driver_id}/info").get(get_driver_information));
+    cfg.service(web::resource("/{driver_id}/rating-history").get(get_rating_history));
+    cfg.service(web::resource("/{driver_id}/head_to_head/{opponent_id}").get(get_head_to_head));
 }
 
 async fn test() -> ApiResponse<()> {
@@ -24,6 +29,7 @@ async fn get_all_drivers(pool: web::Data<Pool<Postgres>>) -> ApiResponse<Vec<Dri
         "SELECT driver_id, username, driver_number, driver_image_url, country, birthday FROM driver"
     )
     .fetch_all(pool)
+    .timed("get_all_drivers")
     .await;
 
     match query {
@@ -36,133 +42,188 @@ async fn get_all_drivers(pool: web::Data<Pool<Postgres>>) -> ApiResponse<Vec<Dri
 
 }
 
-async fn get_driver_information(
+#[derive(Debug, serde::Deserialize)]
+struct SearchQuery {
+    name: String,
+}
+
+//Matches current usernames as well as names a driver used in earlier seasons
+async fn search_drivers(
+    pool: web::Data<Pool<Postgres>>,
+    query: web::Query<SearchQuery>,
+) -> ApiResponse<Vec<DriverSearchResult>> {
+    let pool = pool.get_ref();
+    let name = query.into_inner().name.trim().to_lowercase();
+    if name.is_empty() {
+        return ApiResponse::new_bad_request("Search name can not be empty");
+    }
+    let pattern = format!("%{}%", name.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_"));
+
+    let rows = sqlx::query!(
+        r#"
+        SELECT DISTINCT ON (d.driver_id)
+            d.driver_id, d.username, d.driver_number, d.driver_image_url, d.country, d.birthday,
+            da.username AS "alias?", da.first_season AS "first_season?", da.last_season
+        FROM driver d
+        LEFT JOIN driver_alias da ON da.driver_id = d.driver_id AND da.username ILIKE $1
+        WHERE d.username ILIKE $1 OR da.driver_alias_id IS NOT NULL
+        ORDER BY d.driver_id, (d.username ILIKE $1) DESC, da.first_season DESC;
+        "#,
+        pattern
+    )
+    .fetch_all(pool)
+    .timed("search_drivers")
+    .await;
+
+    match rows {
+        Ok(rows) => {
+            let results = rows
+                .into_iter()
+                .map(|row| {
+                    let current_match = row.username.to_lowercase().contains(&name);
+                    let matched_alias = match (row.alias, row.first_season) {
+                        (Some(username), Some(first_season)) if !current_match => Some(DriverAlias {
+                            username,
+                            first_season,
+                            last_season: row.last_season,
+                        }),
+                        _ => None,
+                    };
+                    DriverSearchResult {
+                        driver_info: DriverInfo {
+                            driver_id: row.driver_id,
+                            username: row.username,
+                            driver_number: row.driver_number,
+                            driver_image_url: row.driver_image_url,
+                            country: row.country,
+                            birthday: row.birthday,
+                        },
+                        matched_alias,
+                    }
+                })
+                .collect();
+            ApiResponse::new_ok("Successfully searched drivers", results)
+        }
+        Err(e) => {
+            warn!("Failed to search drivers: {:?}", e);
+            ApiResponse::new_internal_error("Failed to search drivers")
+        }
+    }
+}
+
+async fn get_driver_aliases(
     pool: web::Data<Pool<Postgres>>,
     driver_id: web::Path<i32>,
-) -> ApiResponse<Driver> {
+) -> ApiResponse<Vec<DriverAlias>> {
     let pool = pool.get_ref();
-    let driver_id: i32 = driver_id.into_inner();
+    let driver_id = driver_id.into_inner();
 
-    let ref_pool = pool.clone();
-    let season_results_handle : JoinHandle<Result<Vec<SeasonResult>,sqlx::Error>> = tokio::spawn(async move {db::get_season_results(&ref_pool, driver_id).await});
+    if let Err(e) = db::get_driver_info(pool, driver_id).timed("get_driver_info").await {
+        if let sqlx::Error::RowNotFound = e {
+            return ApiResponse::new_not_found_error("Driver not found");
+        }
+        warn!("Failed to fetch driver information: {:?}", e);
+        return ApiResponse::new_internal_error("Failed to fetch driver information");
+    }
 
-    let driver_info = sqlx::query_as!(
-        DriverInfo,
-        "SELECT driver_id, username, driver_number, driver_image_url, birthday, country FROM driver WHERE driver_id = $1",
+    let aliases = sqlx::query_as!(
+        DriverAlias,
+        "SELECT username, first_season, last_season FROM driver_alias WHERE driver_id = $1 ORDER BY first_season",
         driver_id
     )
-    .fetch_one(pool)
+    .fetch_all(pool)
+    .timed("get_driver_aliases")
     .await;
 
-    let driver_info = match driver_info {
-        Ok(driver_info) => driver_info,
+    match aliases {
+        Ok(aliases) => ApiResponse::new_ok("Successfully fetched aliases", aliases),
         Err(e) => {
-            if let sqlx::Error::RowNotFound = e {
-                info!("Driver not found: {:?}", e);
-                return ApiResponse::new_not_found_error("Driver not found");
-            } else {
-                warn!("Failed to fetch driver information: {:?}", e);
-                return ApiResponse::new_internal_error("Failed to fetch driver information");
-            }
+            warn!("Failed to fetch driver aliases: {:?}", e);
+            ApiResponse::new_internal_error("Failed to fetch driver aliases")
         }
-    };
-
-    let seat_id = sqlx::query!(
-        "SELECT seat_id FROM drives_in WHERE driver_id = $1",
-        driver_id
-    ).fetch_all(pool).await;
+    }
+}
 
-    let seat_id: Vec<i32> = match seat_id{
-        Ok(seat_id) => seat_id.iter().map(|x| x.seat_id).collect(),
+//Current rating of every driver that has raced, best first
+async fn get_driver_ratings(
+    pool: web::Data<Pool<Postgres>>,
+    query: web::Query<BotQuery>,
+) -> ApiResponse<Vec<DriverRating>> {
+    match db::get_driver_ratings(pool.get_ref(), query.bots).timed("get_driver_ratings").await {
+        Ok(ratings) => ApiResponse::new_ok("Successfully fetched driver ratings", ratings),
         Err(e) => {
-            warn!("Failed to fetch seat information: {:?}", e);
-            return ApiResponse::new_internal_error("Failed to fetch seat information");
+            warn!("Failed to fetch driver ratings: {:?}", e);
+            ApiResponse::new_internal_error("Failed to fetch driver ratings")
         }
-    };
+    }
+}
 
-    let mut joinset = JoinSet::new();
-    for &seat_id in seat_id.iter(){
-        let pool = pool.clone();
-        joinset.spawn(async move {
-            let race_results = sqlx::query_as!(
-                RaceResult,
-                r#"
-                SELECT 
-                    result.position AS position, 
-                    bot_result, 
-                    result.pole AS pole, 
-                    points.leading_lap AS leading_lap, 
-                    points.fastest_lap as fastest_lap, 
-                    qualy_result, 
-                    result.season as season, 
-                    races.race_id as race_id, 
-                    race_name, 
-                    points
-                FROM result
-                JOIN races ON result.race_id = races.race_id
-                JOIN points ON result.season = points.season 
-                    AND result.position = points.position 
-                    AND result.pole = points.pole 
-                    AND result.leading_lap = points.leading_lap 
-                    AND result.fastest_lap = points.fastest_lap 
-                    AND races.season = points.season
-                WHERE result_id IN (SELECT result_id FROM has_result WHERE seat_id = $1);
-                "#,
-                seat_id
-            ).fetch_all(&pool).await;
-    
-    
-            let race_results = match race_results{
-                Ok(teams) => teams,
-                Err(e) => {
-                    warn!("Failed to fetch result information : {:?}", e);
-                    return Err(ApiResponse::new_internal_error("Unabbe to fetch teams"));
-                }
-            };
-    
-            
-            let teams = sqlx::query_as!(
-                Team,
-                "SELECT team_id, name, color FROM team WHERE team_id IN (SELECT team_id FROM drives_for WHERE drives_for.seat_id = $1)",
-                seat_id
-            ).fetch_one(&pool).await;
-    
-            let teams = match teams {
-                Ok(teams) => teams,
-                Err(e) => {
-                    warn!("Failed to fetch team information: {:?}", e);
-                    return Err(ApiResponse::new_internal_error("Failed to fetch team information"));
-                }
-            };
-    
-            Ok(Seat{
-                seat_id,
-                team: teams,
-                results: race_results,
-            })
-        });
+async fn get_rating_history(
+    pool: web::Data<Pool<Postgres>>,
+    driver_id: web::Path<i32>,
+    query: web::Query<BotQuery>,
+) -> ApiResponse<Vec<RatingHistoryEntry>> {
+    let pool = pool.get_ref();
+    let driver_id = driver_id.into_inner();
+
+    if let Err(e) = db::get_driver_info(pool, driver_id).timed("get_driver_info").await {
+        if let sqlx::Error::RowNotFound = e {
+            return ApiResponse::new_not_found_error("Driver not found");
+        }
+        warn!("Failed to fetch driver information: {:?}", e);
+        return ApiResponse::new_internal_error("Failed to fetch driver information");
     }
 
-    let mut seats : Vec<Seat> = Vec::new();
-    while let Some(seat) = joinset.join_next().await{
-        match seat.unwrap(){
-            Ok(seat) => seats.push(seat),
-            Err(e) => return e,
+    match db::get_rating_history(pool, driver_id, query.bots).timed("get_rating_history").await {
+        Ok(history) => ApiResponse::new_ok("Successfully fetched rating history", history),
+        Err(e) => {
+            warn!("Failed to fetch rating history: {:?}", e);
+            ApiResponse::new_internal_error("Failed to fetch rating history")
         }
     }
+}
 
-    let season_results = match season_results_handle.await{
-        Ok(Ok(results)) => results,
-        Ok(e) => {
-            warn!("Something went wrong querying database: {e:?}");
-            return ApiResponse::new_internal_error("Something went wrong querying the database");
-        },
+async fn get_driver_information(
+    pool: web::Data<Pool<Postgres>>,
+    driver_id: web::Path<i32>,
+    query: web::Query<BotQuery>,
+) -> ApiResponse<Driver> {
+    let pool = pool.get_ref();
+    let driver_id: i32 = driver_id.into_inner();
+    let bots = query.bots;
+
+    //A fixed number of queries, however many seats the driver had
+    let payload = futures::try_join!(
+        db::get_driver_info(pool, driver_id).timed("get_driver_info"),
+        db::get_driver_seats(pool, driver_id).timed("get_driver_seats"),
+        db::get_season_results(pool, driver_id).timed("get_season_results"),
+        db::get_teammate_results(pool, None, None, Some(driver_id)).timed("get_teammate_results"),
+    );
+
+    let (driver_info, mut seats, season_results, teammate_results) = match payload {
+        Ok(payload) => payload,
+        Err(sqlx::Error::RowNotFound) => {
+            info!("Driver not found: {}", driver_id);
+            return ApiResponse::new_not_found_error("Driver not found");
+        }
         Err(e) => {
-            warn!("Failed to spawn thread: {e:?}");
-            return ApiResponse::new_internal_error("Something went wrong handling thread");
+            warn!("Failed to fetch driver information: {:?}", e);
+            return ApiResponse::new_internal_error("Failed to fetch driver information");
         }
     };
 
+    let bot_results = seats
+        .iter()
+        .flat_map(|seat| seat.results.iter())
+        .filter(|result| result.bot_result)
+        .count() as i32;
+    seats
+        .iter_mut()
+        .for_each(|seat| seat.results.retain(|result| bots.keeps(result.bot_result)));
+
+    let teammate_battles =
+        teammates::battles_of(teammates::teammate_battles(&teammate_results, bots), driver_id);
+
     ApiResponse::new_ok("succes", Driver{
         driver_id: driver_info.driver_id,
         username: driver_info.username,
@@ -171,6 +232,143 @@ async fn get_driver_information(
         country: driver_info.country,
         birthday: driver_info.birthday,
         seats,
-        season_results
+        season_results,
+        teammate_battles,
+        bot_results,
+    })
+}
+
+async fn get_head_to_head(
+    pool: web::Data<Pool<Postgres>>,
+    path: web::Path<(i32, i32)>,
+    query: web::Query<BotQuery>,
+) -> ApiResponse<HeadToHead> {
+    let pool = pool.get_ref();
+    let (driver_id, opponent_id) = path.into_inner();
+    let bots = query.bots;
+    if driver_id == opponent_id {
+        return ApiResponse::new_bad_request("A driver can not be compared with themselves");
+    }
+
+    let mut drivers = Vec::with_capacity(2);
+    for id in [driver_id, opponent_id] {
+        match db::get_driver_info(pool, id).timed("get_driver_info").await {
+            Ok(driver) => drivers.push(driver),
+            Err(sqlx::Error::RowNotFound) => {
+                return ApiResponse::new_not_found_error("Driver not found");
+            }
+            Err(e) => {
+                warn!("Failed to fetch driver information: {:?}", e);
+                return ApiResponse::new_internal_error("Failed to fetch driver information");
+            }
+        }
+    }
+    let opponent = drivers.pop().unwrap();
+    let driver = drivers.pop().unwrap();
+
+    let races = sqlx::query!(
+        r#"
+        SELECT
+            races.race_id,
+            races.race_name,
+            races.round,
+            races.season,
+            a.position AS driver_position,
+            a.race_time AS driver_race_time,
+            a.best_lap_time AS driver_best_lap_time,
+            a.bot_result AS driver_bot_result,
+            b.position AS opponent_position,
+            b.race_time AS opponent_race_time,
+            b.best_lap_time AS opponent_best_lap_time,
+            b.bot_result AS opponent_bot_result
+        FROM races
+        JOIN result a ON a.race_id = races.race_id
+        JOIN has_result ha ON ha.result_id = a.result_id
+        JOIN drives_in da ON da.seat_id = ha.seat_id
+        JOIN result b ON b.race_id = races.race_id
+        JOIN has_result hb ON hb.result_id = b.result_id
+        JOIN drives_in db ON db.seat_id = hb.seat_id
+        WHERE da.driver_id = $1 AND db.driver_id = $2
+        ORDER BY races.season, races.round;
+        "#,
+        driver_id,
+        opponent_id
+    )
+    .fetch_all(pool)
+    .timed("get_head_to_head")
+    .await;
+
+    let races: Vec<HeadToHeadRace> = match races {
+        Ok(races) => races
+            .into_iter()
+            .filter(|race| bots.keeps(race.driver_bot_result) && bots.keeps(race.opponent_bot_result))
+            .map(|race| HeadToHeadRace {
+                race_id: race.race_id,
+                race_name: race.race_name,
+                round: race.round,
+                season: race.season,
+                driver_position: Position::new(race.driver_position),
+                opponent_position: Position::new(race.opponent_position),
+                driver_bot_result: race.driver_bot_result,
+                opponent_bot_result: race.opponent_bot_result,
+                driver_race_time: race.driver_race_time,
+                opponent_race_time: race.opponent_race_time,
+                time_gap: race
+                    .driver_race_time
+                    .zip(race.opponent_race_time)
+                    .map(|(driver, opponent)| driver - opponent),
+                driver_best_lap_time: race.driver_best_lap_time,
+                opponent_best_lap_time: race.opponent_best_lap_time,
+            })
+            .collect(),
+        Err(e) => {
+            warn!("Failed to fetch head to head results: {:?}", e);
+            return ApiResponse::new_internal_error("Failed to fetch head to head results");
+        }
+    };
+
+    let (mut driver_ahead, mut opponent_ahead) = (0, 0);
+    for race in races.iter() {
+        match race.driver_position.cmp(&race.opponent_position) {
+            std::cmp::Ordering::Less => driver_ahead += 1,
+            std::cmp::Ordering::Greater => opponent_ahead += 1,
+            std::cmp::Ordering::Equal => {}
+        }
+    }
+
+    let bot_races = bots.flags().then(|| {
+        races
+            .iter()
+            .filter(|race| race.driver_bot_result || race.opponent_bot_result)
+            .count() as i32
+    });
+
+    ApiResponse::new_ok("Successfully fetched head to head", HeadToHead {
+        driver,
+        opponent,
+        driver_ahead,
+        opponent_ahead,
+        bot_races,
+        races,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::{test, App};
+    use sqlx::postgres::PgPoolOptions;
+
+    use super::*;
+
+    #[actix_web::test]
+    async fn head_to_head_against_themselves_is_rejected() {
+        //Never connects, the request has to be turned away before the database is asked
+        let pool = PgPoolOptions::new().connect_lazy("postgres://localhost/unused").unwrap();
+        let app = test::init_service(App::new().app_data(web::Data::new(pool)).configure(config)).await;
+
+        let request = test::TestRequest::get().uri("/4/head_to_head/4").to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+}
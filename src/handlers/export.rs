@@ -0,0 +1,71 @@
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use actix_web::{web, Either, HttpResponse};
+use sqlx::{Pool, Postgres};
+use tracing::warn;
+
+use crate::exporter::{self, Export};
+use crate::models::api_response::ApiResponse;
+use crate::utils::metrics::Timed;
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::resource("/season/{season:\\d+}.csv").get(export_season));
+    cfg.service(web::resource("/results.csv").get(export_results));
+    cfg.service(web::resource("/league.json").get(export_league));
+}
+
+fn streaming_response(
+    pool: &Pool<Postgres>,
+    export: Export,
+    content_type: &'static str,
+    filename: String,
+) -> HttpResponse {
+    let chunks = exporter::spawn(pool.clone(), export);
+    let body = futures::stream::unfold(chunks, |mut chunks| async move {
+        let chunk = chunks.recv().await?;
+        Some((chunk.map_err(actix_web::error::ErrorInternalServerError), chunks))
+    });
+
+    HttpResponse::Ok()
+        .content_type(content_type)
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(filename)],
+        })
+        .streaming(body)
+}
+
+async fn export_season(
+    pool: web::Data<Pool<Postgres>>,
+    season: web::Path<i32>,
+) -> Either<HttpResponse, ApiResponse<()>> {
+    let pool = pool.get_ref();
+    let season = season.into_inner();
+
+    let exists = sqlx::query_scalar!("SELECT season FROM seasons WHERE season = $1", season)
+        .fetch_optional(pool)
+        .timed("season_exists")
+        .await;
+    match exists {
+        Ok(Some(_)) => {}
+        Ok(None) => return Either::Right(ApiResponse::new_not_found_error("Season not found")),
+        Err(e) => {
+            warn!("Failed to fetch season: {:?}", e);
+            return Either::Right(ApiResponse::new_internal_error("Failed to fetch season"));
+        }
+    }
+
+    Either::Left(streaming_response(
+        pool,
+        Export::SeasonCsv(season),
+        "text/csv",
+        format!("season_{season}.csv"),
+    ))
+}
+
+async fn export_results(pool: web::Data<Pool<Postgres>>) -> HttpResponse {
+    streaming_response(pool.get_ref(), Export::ResultsCsv, "text/csv", "results.csv".into())
+}
+
+async fn export_league(pool: web::Data<Pool<Postgres>>) -> HttpResponse {
+    streaming_response(pool.get_ref(), Export::LeagueJson, "application/json", "league.json".into())
+}
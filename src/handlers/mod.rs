@@ -1,3 +1,7 @@
+pub mod admin;
 pub mod drivers;
+pub mod export;
+pub mod monitoring;
+pub mod races;
 pub mod season;
 pub mod teams;
\ No newline at end of file
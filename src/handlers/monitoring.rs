@@ -0,0 +1,105 @@
+use std::collections::BTreeSet;
+use std::time::Duration;
+
+use actix_web::{web, HttpResponse};
+use prometheus::{Encoder, TextEncoder};
+use sqlx::{Pool, Postgres};
+use tokio::time::timeout;
+use tracing::warn;
+
+use crate::models::api_response::ApiResponse;
+use crate::models::db_objects::{Readiness, VersionInfo};
+use crate::utils::db;
+use crate::utils::metrics::{Timed, METRICS};
+use crate::utils::recalculation::RecalcHandle;
+
+//Probes should answer quickly, a database that takes longer than this counts as unavailable
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::resource("/metrics").get(metrics));
+    cfg.service(web::resource("/health").get(health));
+    cfg.service(web::resource("/ready").get(ready));
+    cfg.service(web::resource("/version").get(version));
+}
+
+//Prometheus scrape target, plain text rather than an ApiResponse so the scraper can parse it
+async fn metrics(pool: web::Data<Pool<Postgres>>) -> HttpResponse {
+    match METRICS.render(pool.get_ref()) {
+        Ok(body) => HttpResponse::Ok()
+            .content_type(TextEncoder::new().format_type())
+            .body(body),
+        Err(e) => {
+            warn!("Failed to encode metrics: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+//Liveness, answers as long as the server can handle requests
+async fn health() -> ApiResponse<()> {
+    ApiResponse::new_ok_no_data("Server is running")
+}
+
+async fn ready(
+    pool: web::Data<Pool<Postgres>>,
+    recalc: web::Data<RecalcHandle>,
+) -> ApiResponse<Readiness> {
+    let applied = db::get_applied_migrations(pool.get_ref()).timed("get_applied_migrations");
+    let applied = match timeout(PROBE_TIMEOUT, applied).await {
+        Ok(Ok(applied)) => Some(applied),
+        Ok(Err(e)) => {
+            warn!("Readiness check could not reach the database: {:?}", e);
+            None
+        }
+        Err(_) => {
+            warn!("Readiness check timed out reaching the database");
+            None
+        }
+    };
+
+    let pending_migrations = match &applied {
+        Some(applied) => {
+            let applied: BTreeSet<i64> = applied.iter().copied().collect();
+            sqlx::migrate!()
+                .iter()
+                .map(|migration| migration.version)
+                .filter(|version| !applied.contains(version))
+                .collect()
+        }
+        None => Vec::new(),
+    };
+
+    let readiness = Readiness {
+        database: applied.is_some(),
+        pending_migrations,
+        recalculation_worker: recalc.is_running(),
+    };
+    if readiness.database && readiness.pending_migrations.is_empty() && readiness.recalculation_worker {
+        ApiResponse::new_ok("Ready", readiness)
+    } else {
+        ApiResponse::new_service_unavailable("Not ready", readiness)
+    }
+}
+
+async fn version(pool: web::Data<Pool<Postgres>>) -> ApiResponse<VersionInfo> {
+    //The schema version is left empty rather than failing the request when the database is down
+    let applied = db::get_applied_migrations(pool.get_ref()).timed("get_applied_migrations");
+    let schema_version = match timeout(PROBE_TIMEOUT, applied).await {
+        Ok(Ok(applied)) => applied.last().copied(),
+        Ok(Err(e)) => {
+            warn!("Failed to fetch schema version: {:?}", e);
+            None
+        }
+        Err(_) => None,
+    };
+
+    ApiResponse::new_ok(
+        "Successfully fetched version",
+        VersionInfo {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            git_hash: env!("GIT_HASH").to_string(),
+            schema_version,
+        },
+    )
+}
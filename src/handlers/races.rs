@@ -0,0 +1,241 @@
+use std::collections::HashMap;
+
+use actix_web::web;
+use itertools::Itertools;
+use sqlx::{Pool, Postgres};
+use tracing::{info, warn};
+
+use crate::importer::{self, ImportPreview, SessionFormat};
+use crate::models::api_response::ApiResponse;
+use crate::models::db_objects::*;
+use crate::utils::db;
+use crate::utils::metrics::Timed;
+use crate::utils::recalculation::{RecalcHandle, Trigger};
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::resource("/{race_id}/qualifying").get(get_qualifying));
+}
+
+//Writes that replace the results of a race, served under /admin
+pub fn admin_config(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::resource("/race/{race_id}/results").post(import_results));
+    cfg.service(web::resource("/race/{race_id}/import").post(import_session_file));
+}
+
+async fn get_race(pool: &Pool<Postgres>, race_id: i32) -> Result<RaceInfo, ApiResponse<()>> {
+    match db::get_race(pool, race_id).timed("get_race").await {
+        Ok(race) => Ok(race),
+        Err(sqlx::Error::RowNotFound) => Err(ApiResponse::new_not_found_error("Race not found")),
+        Err(e) => {
+            warn!("Failed to fetch race: {:?}", e);
+            Err(ApiResponse::new_internal_error("Failed to fetch race"))
+        }
+    }
+}
+
+async fn get_qualifying(
+    pool: web::Data<Pool<Postgres>>,
+    race_id: web::Path<i32>,
+) -> ApiResponse<QualifyingClassification> {
+    let pool = pool.get_ref();
+    let race_id = race_id.into_inner();
+
+    let race = match get_race(pool, race_id).await {
+        Ok(race) => race,
+        Err(e) => return e.into_error(),
+    };
+
+    let rows = sqlx::query!(
+        r#"
+        SELECT
+            q.position,
+            COALESCE(q.grid_position, q.position) AS "grid_position!",
+            q.stage,
+            q.q1_time,
+            q.q2_time,
+            q.q3_time,
+            d.driver_id,
+            d.username,
+            d.driver_number,
+            d.driver_image_url,
+            d.country,
+            d.birthday,
+            t.team_id,
+            t.name,
+            t.color
+        FROM qualifying_result q
+        JOIN drives_in di ON q.seat_id = di.seat_id
+        JOIN driver d ON di.driver_id = d.driver_id
+        JOIN drives_for df ON q.seat_id = df.seat_id
+        JOIN team t ON df.team_id = t.team_id
+        WHERE q.race_id = $1
+        ORDER BY q.position;
+        "#,
+        race_id
+    )
+    .fetch_all(pool)
+    .timed("get_qualifying")
+    .await;
+
+    let rows = match rows {
+        Ok(rows) => rows,
+        Err(e) => {
+            warn!("Failed to fetch qualifying results: {:?}", e);
+            return ApiResponse::new_internal_error("Failed to fetch qualifying results");
+        }
+    };
+
+    let results = rows
+        .into_iter()
+        .map(|row| QualifyingResult {
+            position: row.position,
+            grid_position: row.grid_position,
+            stage: row.stage.into(),
+            q1_time: row.q1_time,
+            q2_time: row.q2_time,
+            q3_time: row.q3_time,
+            best_time: None,
+            gap_to_pole: None,
+            interval: None,
+            driver_info: DriverInfo {
+                driver_id: row.driver_id,
+                username: row.username,
+                driver_number: row.driver_number,
+                driver_image_url: row.driver_image_url,
+                country: row.country,
+                birthday: row.birthday,
+            },
+            team: Team {
+                team_id: row.team_id,
+                name: row.name,
+                color: row.color,
+            },
+        })
+        .collect();
+
+    ApiResponse::new_ok(
+        "Successfully fetched qualifying",
+        QualifyingClassification::new(race, results),
+    )
+}
+
+async fn import_results(
+    pool: web::Data<Pool<Postgres>>,
+    recalc: web::Data<RecalcHandle>,
+    race_id: web::Path<i32>,
+    results: web::Json<Vec<ResultImport>>,
+) -> ApiResponse<usize> {
+    let pool = pool.get_ref();
+    let race_id = race_id.into_inner();
+    let results = results.into_inner();
+
+    if results.is_empty() {
+        return ApiResponse::new_bad_request("No results supplied");
+    }
+    if !results.iter().map(|result| result.seat_id).all_unique() {
+        return ApiResponse::new_bad_request("A seat can only have one result per race");
+    }
+
+    let race = match get_race(pool, race_id).await {
+        Ok(race) => race,
+        Err(e) => return e.into_error(),
+    };
+
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            warn!("Failed to start transaction: {:?}", e);
+            return ApiResponse::new_internal_error("Failed to import results");
+        }
+    };
+
+    let imported = match db::import_race_results(&mut tx, &race, &results)
+        .timed("import_race_results")
+        .await
+    {
+        Ok(imported) => imported,
+        Err(sqlx::Error::Database(e)) if e.is_foreign_key_violation() || e.is_check_violation() => {
+            info!("Rejected result import: {:?}", e);
+            return ApiResponse::new_bad_request("Results reference an unknown seat or contain invalid values");
+        }
+        Err(e) => {
+            warn!("Failed to import results: {:?}", e);
+            return ApiResponse::new_internal_error("Failed to import results");
+        }
+    };
+
+    if let Err(e) = tx.commit().await {
+        warn!("Failed to commit result import: {:?}", e);
+        return ApiResponse::new_internal_error("Failed to import results");
+    }
+    recalc.request(Trigger::Season(race.season));
+
+    ApiResponse::new_ok("Successfully imported results", imported)
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct SessionImportQuery {
+    format: SessionFormat,
+    #[serde(default)]
+    confirm: bool,
+    //Seats for names from the session file, as a JSON object such as {"Driver": 12}
+    #[serde(default)]
+    seats: Option<String>,
+}
+
+//Maps a session file exported by the game onto the race, and writes it when confirm=true
+async fn import_session_file(
+    pool: web::Data<Pool<Postgres>>,
+    recalc: web::Data<RecalcHandle>,
+    race_id: web::Path<i32>,
+    query: web::Query<SessionImportQuery>,
+    body: web::Bytes,
+) -> ApiResponse<ImportPreview> {
+    let pool = pool.get_ref();
+    let race_id = race_id.into_inner();
+
+    let entries = match importer::parse(query.format, &body) {
+        Ok(entries) => entries,
+        Err(e) => return ApiResponse::new_bad_request(e),
+    };
+    let seats: HashMap<String, i32> = match query.seats.as_deref().map(serde_json::from_str).transpose() {
+        Ok(seats) => seats.unwrap_or_default(),
+        Err(e) => return ApiResponse::new_bad_request(format!("Invalid seats: {e}")),
+    };
+
+    let race = match get_race(pool, race_id).await {
+        Ok(race) => race,
+        Err(e) => return e.into_error(),
+    };
+
+    let preview = match importer::build_preview(pool, race, entries, &seats).await {
+        Ok(preview) => preview,
+        Err(e) => {
+            warn!("Failed to map session file: {:?}", e);
+            return ApiResponse::new_internal_error("Failed to map session file");
+        }
+    };
+
+    if !query.confirm {
+        return ApiResponse::new_ok("Preview of the mapped results", preview);
+    }
+    if !preview.is_complete() {
+        return ApiResponse {
+            status_code: 400,
+            message: "Session file could not be fully mapped".into(),
+            data: Some(preview),
+        };
+    }
+
+    match importer::commit(pool, &preview).await {
+        Ok(imported) => {
+            info!("Imported {} results for race {}", imported, race_id);
+            recalc.request(Trigger::Season(preview.race.season));
+            ApiResponse::new_ok("Successfully imported session file", preview)
+        }
+        Err(e) => {
+            warn!("Failed to import session file: {:?}", e);
+            ApiResponse::new_internal_error("Failed to import session file")
+        }
+    }
+}
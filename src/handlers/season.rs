@@ -1,4 +1,4 @@
-use std::result;
+use std::collections::HashMap;
 
 use actix_web::web;
 use sqlx::{Pool, Postgres};
@@ -7,11 +7,19 @@ use tracing::warn;
 
 use crate::models::api_response::ApiResponse;
 use crate::models::db_objects::*;
+use crate::utils::metrics::Timed;
+use crate::utils::{db, scenarios, standings};
 
 pub fn config(cfg: &mut web::ServiceConfig) {
     cfg.service(web::resource("/test").to(test));
     cfg.service(web::resource("/all_seasons").get(get_all_seasons));
     cfg.service(web::resource("{season}/info").get(get_season_info));
+    cfg.service(web::resource("{season}/progression").get(get_season_progression));
+    cfg.service(
+        web::resource("{season}/scenarios")
+            .get(get_season_scenarios)
+            .post(evaluate_season_scenarios),
+    );
 }
 
 async fn test() -> ApiResponse<()> {
@@ -23,6 +31,7 @@ async fn get_all_seasons(pool: web::Data<Pool<Postgres>>) -> ApiResponse<Vec<Sea
 
     let seasons = sqlx::query_as!(Season, "SELECT season, season_name FROM seasons")
         .fetch_all(pool)
+        .timed("get_seasons")
         .await;
 
     match seasons {
@@ -37,9 +46,11 @@ async fn get_all_seasons(pool: web::Data<Pool<Postgres>>) -> ApiResponse<Vec<Sea
 async fn get_season_info(
     pool: web::Data<Pool<Postgres>>,
     season: web::Path<i32>,
+    query: web::Query<BotQuery>,
 ) -> ApiResponse<SeasonInfo> {
     let pool = pool.get_ref();
     let season_number = season.into_inner();
+    let bots = query.bots;
 
     let season = {
         let season = sqlx::query_as!(
@@ -48,6 +59,7 @@ async fn get_season_info(
             season_number
         )
         .fetch_one(pool)
+        .timed("get_season")
         .await;
         match season {
             Ok(season) => season,
@@ -61,59 +73,453 @@ async fn get_season_info(
         }
     };
 
-    let results = {
-        let query: Result<Vec<PersonalResult>, sqlx::Error> = sqlx::query_as("
-            SELECT result.position, result.bot_result, result.pole, result.leading_lap, result.fastest_lap, result.qualy_result, result.season, result.race_id, has_result.seat_id, has_result.result_id, drives_in.driver_id, drives_in.seat_id, d.driver_id, d.username, d.driver_number, d.driver_image_url, drives_for.seat_id, drives_for.team_id, t.team_id, t.name, t.color, r.race_id, r.race_name, r.season, p.season, p.position, p.pole, p.leading_lap, p.fastest_lap, p.position, bot_result, p.pole, p.leading_lap, p.fastest_lap, qualy_result, r.season, r.race_id, race_name, points, d.driver_id, username, driver_number, driver_image_url, t.team_id, name, color
-            FROM result
-            JOIN has_result ON result.result_id = has_result.result_id
-            JOIN drives_in ON drives_in.seat_id = has_result.seat_id
-            JOIN FormulaDestruction.driver d on drives_in.driver_id = d.driver_id
-            JOIN drives_for on drives_in.seat_id = drives_for.seat_id
-            JOIN FormulaDestruction.team t on drives_for.team_id = t.team_id
-            JOIN FormulaDestruction.races r on result.race_id = r.race_id
-            JOIN FormulaDestruction.points p on result.season = p.season and result.position = p.position and result.pole = p.pole and result.leading_lap = p.leading_lap and result.fastest_lap = p.fastest_lap
-            WHERE result.season = ?;"
-        )
-        .bind(season_number)
-        .fetch_all(pool).await;
+    let results = sqlx::query!(
+        r#"
+        SELECT
+            result.position AS result_position,
+            result.bot_result AS result_bot_result,
+            p.pole AS result_pole,
+            result.leading_lap AS result_leading_lap,
+            result.fastest_lap AS result_fastest_lap,
+            COALESCE(q.position, result.qualy_result) AS result_qualy_result,
+            result.season AS result_season,
+            r.race_id AS race_id,
+            r.race_name AS race_name,
+            r.round AS race_round,
+            p.points AS points,
+            result.race_time AS result_race_time,
+            result.gap_to_winner AS result_gap_to_winner,
+            result.laps_completed AS result_laps_completed,
+            result.best_lap_time AS result_best_lap_time,
+            result.pit_stops AS result_pit_stops,
+            d.driver_id AS driver_id,
+            COALESCE(da.username, d.username) AS "driver_username!",
+            d.driver_number AS driver_number,
+            d.driver_image_url AS driver_image_url,
+            d.country AS driver_country,
+            d.birthday AS driver_birthday,
+            t.team_id AS team_id,
+            t.name AS team_name,
+            t.color AS team_color
+        FROM result
+        JOIN has_result hr ON result.result_id = hr.result_id
+        JOIN drives_in di ON hr.seat_id = di.seat_id
+        JOIN driver d ON di.driver_id = d.driver_id
+        JOIN drives_for df ON hr.seat_id = df.seat_id
+        JOIN team t ON df.team_id = t.team_id
+        JOIN races r ON result.race_id = r.race_id
+        LEFT JOIN qualifying_result q ON q.race_id = result.race_id AND q.seat_id = hr.seat_id
+        JOIN points p ON result.season = p.season
+            AND result.position = p.position
+            AND COALESCE(q.position = 1, result.pole) = p.pole
+            AND result.leading_lap = p.leading_lap
+            AND result.fastest_lap = p.fastest_lap
+        LEFT JOIN LATERAL (
+            SELECT username FROM driver_alias
+            WHERE driver_alias.driver_id = d.driver_id
+                AND result.season BETWEEN first_season AND COALESCE(last_season, result.season)
+            ORDER BY first_season DESC LIMIT 1
+        ) da ON true
+        WHERE result.season = $1
+        ORDER BY r.round, result.position;
+        "#,
+        season_number
+    )
+    .fetch_all(pool)
+    .timed("get_season_info")
+    .await;
+
+    let results: Vec<PersonalResult> = match results {
+        Ok(rows) => rows
+            .into_iter()
+            .filter(|row| bots.keeps(row.result_bot_result))
+            .map(|row| PersonalResult {
+                race_result: RaceResult {
+                    position: Position::new(row.result_position),
+                    bot_result: row.result_bot_result,
+                    pole: row.result_pole,
+                    leading_lap: row.result_leading_lap,
+                    fastest_lap: row.result_fastest_lap,
+                    qualy_result: row.result_qualy_result,
+                    season: row.result_season,
+                    race_id: row.race_id,
+                    race_name: row.race_name,
+                    round: row.race_round,
+                    points: row.points,
+                    race_time: row.result_race_time,
+                    gap_to_winner: row.result_gap_to_winner,
+                    laps_completed: row.result_laps_completed,
+                    best_lap_time: row.result_best_lap_time,
+                    pit_stops: row.result_pit_stops,
+                },
+                driver_info: DriverInfo {
+                    driver_id: row.driver_id,
+                    username: row.driver_username,
+                    driver_number: row.driver_number,
+                    driver_image_url: row.driver_image_url,
+                    country: row.driver_country,
+                    birthday: row.driver_birthday,
+                },
+                team: Team {
+                    team_id: row.team_id,
+                    name: row.team_name,
+                    color: row.team_color,
+                },
+            })
+            .collect(),
+        Err(e) => {
+            warn!("failed to fetch results: {:?}", e);
+            return ApiResponse::new_internal_error("Failed to fetch results");
+        }
+    };
+
+    let races = group_races(season_number, results);
+
+    let season = SeasonInfo { season, races };
+
+    ApiResponse::new_ok("Successfully fetched season", season)
+}
+
+async fn check_season(pool: &Pool<Postgres>, season: i32) -> Result<(), ApiResponse<()>> {
+    let exists = sqlx::query_scalar!("SELECT season FROM seasons WHERE season = $1", season)
+        .fetch_optional(pool)
+        .timed("season_exists")
+        .await;
+    match exists {
+        Ok(Some(_)) => Ok(()),
+        Ok(None) => Err(ApiResponse::new_not_found_error("Season not found")),
+        Err(e) => {
+            warn!("Failed to fetch season: {:?}", e);
+            Err(ApiResponse::new_internal_error("Failed to fetch season"))
+        }
+    }
+}
+
+//Standings after every round for the championship charts
+async fn get_season_progression(
+    pool: web::Data<Pool<Postgres>>,
+    season: web::Path<i32>,
+    query: web::Query<BotQuery>,
+) -> ApiResponse<SeasonProgression> {
+    let pool = pool.get_ref();
+    let season_number = season.into_inner();
+
+    if let Err(e) = check_season(pool, season_number).await {
+        return e.into_error();
+    }
+
+    let results = match db::get_progression_results(pool, season_number)
+        .timed("get_progression_results")
+        .await
+    {
+        Ok(results) => results,
+        Err(e) => {
+            warn!("failed to fetch results: {:?}", e);
+            return ApiResponse::new_internal_error("Failed to fetch results");
+        }
+    };
+
+    ApiResponse::new_ok(
+        "Successfully fetched season progression",
+        standings::build_progression(season_number, results, query.bots),
+    )
+}
+
+async fn get_season_scenarios(
+    pool: web::Data<Pool<Postgres>>,
+    season: web::Path<i32>,
+    query: web::Query<BotQuery>,
+) -> ApiResponse<SeasonScenarios> {
+    season_scenarios(pool.get_ref(), season.into_inner(), Vec::new(), query.bots).await
+}
+
+//Same as the plain scenarios, but with made up results for remaining races. Nothing is written
+async fn evaluate_season_scenarios(
+    pool: web::Data<Pool<Postgres>>,
+    season: web::Path<i32>,
+    query: web::Query<BotQuery>,
+    hypothetical: web::Json<Vec<HypotheticalRace>>,
+) -> ApiResponse<SeasonScenarios> {
+    season_scenarios(pool.get_ref(), season.into_inner(), hypothetical.into_inner(), query.bots).await
+}
 
-        match query {
-            Ok(result) => result,
+async fn season_scenarios(
+    pool: &Pool<Postgres>,
+    season_number: i32,
+    hypothetical: Vec<HypotheticalRace>,
+    bots: BotPolicy,
+) -> ApiResponse<SeasonScenarios> {
+    if let Err(e) = check_season(pool, season_number).await {
+        return e.into_error();
+    }
+
+    let data = futures::try_join!(
+        db::get_progression_results(pool, season_number).timed("get_progression_results"),
+        db::get_remaining_races(pool, season_number).timed("get_remaining_races"),
+        db::get_points_scheme(pool, season_number).timed("get_points_scheme"),
+    );
+    let (mut results, mut remaining, points) = match data {
+        Ok(data) => data,
+        Err(e) => {
+            warn!("Failed to fetch season scenario data: {:?}", e);
+            return ApiResponse::new_internal_error("Failed to fetch season data");
+        }
+    };
+
+    let mut hypothetical_races = Vec::with_capacity(hypothetical.len());
+    let mut warnings = Vec::new();
+    let mut assumed = Vec::new();
+    if !hypothetical.is_empty() {
+        let driver_ids: Vec<i32> = hypothetical
+            .iter()
+            .flat_map(|race| race.results.iter().map(|result| result.driver_id))
+            .unique()
+            .collect();
+        let seat_ids: Vec<i32> = hypothetical
+            .iter()
+            .flat_map(|race| race.results.iter().filter_map(|result| result.seat_id))
+            .unique()
+            .collect();
+        let drivers = futures::try_join!(
+            db::get_current_seats(pool, season_number, &driver_ids).timed("get_current_seats"),
+            db::get_seats(pool, &seat_ids).timed("get_seats"),
+            sqlx::query!("SELECT driver_id, username FROM driver WHERE driver_id = ANY($1)", &driver_ids)
+                .fetch_all(pool)
+                .timed("get_driver_names"),
+        );
+        let (seats, chosen, usernames) = match drivers {
+            Ok(drivers) => drivers,
             Err(e) => {
-                warn!("failed to fetch results: {:?}", e);
-                return ApiResponse::new_internal_error("Failed to fetch results");
+                warn!("Failed to fetch drivers for scenarios: {:?}", e);
+                return ApiResponse::new_internal_error("Failed to fetch drivers");
+            }
+        };
+        let usernames: HashMap<i32, String> =
+            usernames.into_iter().map(|row| (row.driver_id, row.username)).collect();
+        let seats: HashMap<i32, CurrentSeat> =
+            seats.into_iter().map(|seat| (seat.driver_id, seat)).collect();
+        let chosen: HashMap<i32, CurrentSeat> =
+            chosen.into_iter().map(|seat| (seat.seat_id, seat)).collect();
+
+        for race in hypothetical {
+            let Some(index) = remaining.iter().position(|r| r.race_id == race.race_id) else {
+                return ApiResponse::new_bad_request(format!(
+                    "Race {} is not a remaining race of this season",
+                    race.race_id
+                ));
+            };
+            let info = remaining.remove(index);
+            if !race.results.iter().map(|result| result.driver_id).all_unique() {
+                return ApiResponse::new_bad_request(format!(
+                    "Race {} has more than one result for a driver",
+                    race.race_id
+                ));
             }
+
+            for result in &race.results {
+                let seat = match result.seat_id {
+                    Some(seat_id) => match chosen.get(&seat_id) {
+                        Some(seat) if seat.driver_id == result.driver_id => Some(seat),
+                        _ => {
+                            return ApiResponse::new_bad_request(format!(
+                                "Seat {} is not a seat of driver {}",
+                                seat_id, result.driver_id
+                            ));
+                        }
+                    },
+                    None => seats.get(&result.driver_id),
+                };
+                let (Some(username), Some(seat)) = (usernames.get(&result.driver_id), seat) else {
+                    return ApiResponse::new_bad_request(format!(
+                        "Driver {} has no seat",
+                        result.driver_id
+                    ));
+                };
+                //The points count for the team of their latest seat, which may be one from an earlier season
+                if seat.assumed && !assumed.contains(&result.driver_id) {
+                    assumed.push(result.driver_id);
+                    warnings.push(format!(
+                        "Driver '{}' has no result in season {}, their latest seat with '{}' was assumed",
+                        username, season_number, seat.name
+                    ));
+                }
+                results.push(ProgressionResult {
+                    race_id: info.race_id,
+                    race_name: info.race_name.clone(),
+                    round: info.round,
+                    driver_id: result.driver_id,
+                    username: username.clone(),
+                    team: Team {
+                        team_id: seat.team_id,
+                        name: seat.name.clone(),
+                        color: seat.color.clone(),
+                    },
+                    position: result.position,
+                    points: scenarios::points_for(&points, result),
+                    bot_result: result.bot_result,
+                });
+            }
+            hypothetical_races.push(info.race_id);
         }
-    };
+    }
+
+    let mut scenarios = scenarios::build_scenarios(
+        season_number,
+        results,
+        remaining,
+        hypothetical_races,
+        &points,
+        bots,
+    );
+    scenarios.warnings = warnings;
+    ApiResponse::new_ok("Successfully calculated season scenarios", scenarios)
+}
 
-    let races: Vec<Race> = results
-        .iter()
+//Splits the results into races in calendar order, each classified by finishing position
+fn group_races(season_number: i32, mut results: Vec<PersonalResult>) -> Vec<Race> {
+    results.sort_by_key(|x| {
+        (
+            x.race_result.round,
+            x.race_result.race_id,
+            x.race_result.position,
+            std::cmp::Reverse(x.race_result.laps_completed),
+        )
+    });
+
+    results
+        .into_iter()
         .chunk_by(|x| x.race_result.race_id)
         .into_iter()
-        .flat_map(|mut race| {
-            let first = race.1.next();
-
-            match first {
-                Some(first) => {
-                    let mut races: Vec<PersonalResult> = race.1.cloned().collect();
-                    races.push(first.clone());
-
-                    races.sort_by(|a: &PersonalResult, b| {
-                        a.race_result.race_id.cmp(&b.race_result.race_id)
-                    });
-
-                    Some(Race {
-                        race_name: first.race_result.race_name.clone(),
-                        season: season_number,
-                        results: races,
-                    })
-                }
-                None => None,
+        .map(|(race_id, race)| {
+            let results: Vec<PersonalResult> = race.collect();
+            Race {
+                race_id,
+                race_name: results[0].race_result.race_name.clone(),
+                round: results[0].race_result.round,
+                season: season_number,
+                results,
             }
         })
-        .collect();
+        .collect()
+}
 
-    let season = SeasonInfo { season, races };
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    ApiResponse::new_ok("Successfully fetched season", season)
+    fn result(race_id: i32, round: i32, position: i32, driver_id: i32) -> PersonalResult {
+        PersonalResult {
+            race_result: RaceResult {
+                position: Position::new(position),
+                bot_result: false,
+                pole: position == 1,
+                leading_lap: false,
+                fastest_lap: false,
+                qualy_result: Some(position),
+                season: 3,
+                race_id,
+                race_name: format!("Race {race_id}"),
+                round,
+                points: 25 - position,
+                race_time: None,
+                gap_to_winner: None,
+                laps_completed: Some(50),
+                best_lap_time: None,
+                pit_stops: Some(1),
+            },
+            driver_info: DriverInfo {
+                driver_id,
+                username: format!("driver{driver_id}"),
+                driver_number: driver_id,
+                driver_image_url: String::new(),
+                country: "NL".into(),
+                birthday: None,
+            },
+            team: Team {
+                team_id: 1,
+                name: "Team".into(),
+                color: None,
+            },
+        }
+    }
+
+    fn drivers(race: &Race) -> Vec<i32> {
+        race.results.iter().map(|r| r.driver_info.driver_id).collect()
+    }
+
+    #[test]
+    fn groups_ordered_results_per_race() {
+        let results = vec![
+            result(1, 1, 1, 10),
+            result(1, 1, 2, 11),
+            result(1, 1, 3, 12),
+            result(2, 2, 1, 11),
+            result(2, 2, 2, 12),
+        ];
+
+        let races = group_races(3, results);
+
+        assert_eq!(races.len(), 2);
+        assert_eq!(races[0].race_name, "Race 1");
+        assert_eq!(races[1].race_name, "Race 2");
+        assert_eq!(drivers(&races[0]), vec![10, 11, 12]);
+        assert_eq!(drivers(&races[1]), vec![11, 12]);
+        assert!(races.iter().all(|race| race.season == 3));
+    }
+
+    #[test]
+    fn sorts_unsorted_results_into_calendar_and_classification_order() {
+        //Race 7 was created later but is the opening round of the season
+        let mut dnf = result(5, 2, 101, 13);
+        dnf.race_result.laps_completed = Some(12);
+        let mut late_dnf = result(5, 2, 101, 14);
+        late_dnf.race_result.laps_completed = Some(40);
+        let results = vec![
+            result(5, 2, 100, 15),
+            result(7, 1, 2, 11),
+            dnf,
+            result(5, 2, 2, 10),
+            result(7, 1, 111, 12),
+            result(5, 2, 111, 16),
+            result(7, 1, 1, 10),
+            late_dnf,
+            result(5, 2, 1, 11),
+        ];
+
+        let races = group_races(3, results);
+
+        assert_eq!(races.len(), 2);
+        assert_eq!((races[0].race_id, races[0].round), (7, 1));
+        assert_eq!((races[1].race_id, races[1].round), (5, 2));
+        assert_eq!(drivers(&races[0]), vec![10, 11, 12]);
+        assert_eq!(drivers(&races[1]), vec![11, 10, 14, 13, 16, 15]);
+    }
+
+    #[test]
+    fn season_info_has_expected_shape() {
+        let season = SeasonInfo {
+            season: Season {
+                season: 3,
+                season_name: "Season 3".into(),
+            },
+            races: group_races(3, vec![result(1, 1, 1, 10)]),
+        };
+
+        let json = serde_json::to_value(&season).unwrap();
+
+        assert_eq!(json["season"]["season"], 3);
+        assert_eq!(json["season"]["season_name"], "Season 3");
+        let race = &json["races"][0];
+        assert_eq!(race["race_id"], 1);
+        assert_eq!(race["race_name"], "Race 1");
+        assert_eq!(race["round"], 1);
+        assert_eq!(race["season"], 3);
+        let result = &race["results"][0];
+        assert_eq!(result["race_result"]["position"], 1);
+        assert_eq!(result["race_result"]["race_id"], 1);
+        assert_eq!(result["race_result"]["points"], 24);
+        assert_eq!(result["driver_info"]["driver_id"], 10);
+        assert_eq!(result["driver_info"]["username"], "driver10");
+        assert_eq!(result["team"]["team_id"], 1);
+    }
 }
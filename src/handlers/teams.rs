@@ -2,10 +2,16 @@ use actix_web::web;
 use sqlx::{Pool, Postgres};
 use tracing::warn;
 
-use crate::models::{api_response::ApiResponse, db_objects::Team};
+use crate::models::{
+    api_response::ApiResponse,
+    db_objects::{BotQuery, Team, TeamTeammates},
+};
+use crate::utils::metrics::Timed;
+use crate::utils::{db, teammates};
 
 pub fn config(cfg: &mut web::ServiceConfig) {
     cfg.service(web::resource("/all_teams").get(get_all_teams));
+    cfg.service(web::resource("/{team_id}/season/{season}/teammates").get(get_teammates));
 }
 
 pub async fn get_all_teams(pool: web::Data<Pool<Postgres>>) -> ApiResponse<Vec<Team>> {
@@ -13,6 +19,7 @@ pub async fn get_all_teams(pool: web::Data<Pool<Postgres>>) -> ApiResponse<Vec<T
 
     let query = sqlx::query_as!(Team, "SELECT name, color, team_id FROM team")
         .fetch_all(pool)
+        .timed("get_all_teams")
         .await;
 
     match query{
@@ -25,3 +32,48 @@ pub async fn get_all_teams(pool: web::Data<Pool<Postgres>>) -> ApiResponse<Vec<T
         }
     }
 }
+
+async fn get_teammates(
+    pool: web::Data<Pool<Postgres>>,
+    path: web::Path<(i32, i32)>,
+    query: web::Query<BotQuery>,
+) -> ApiResponse<TeamTeammates> {
+    let pool = pool.get_ref();
+    let (team_id, season) = path.into_inner();
+
+    let team = sqlx::query_as!(Team, "SELECT team_id, name, color FROM team WHERE team_id = $1", team_id)
+        .fetch_optional(pool)
+        .timed("get_team")
+        .await;
+    let team = match team {
+        Ok(Some(team)) => team,
+        Ok(None) => return ApiResponse::new_not_found_error("Team not found"),
+        Err(e) => {
+            warn!("failed to fetch team: {:?}", e);
+            return ApiResponse::new_internal_error("Failed to fetch team");
+        }
+    };
+
+    let results = match db::get_teammate_results(pool, Some(season), Some(team_id), None)
+        .timed("get_teammate_results")
+        .await
+    {
+        Ok(results) => results,
+        Err(e) => {
+            warn!("failed to fetch teammate results: {:?}", e);
+            return ApiResponse::new_internal_error("Failed to fetch results");
+        }
+    };
+    if results.is_empty() {
+        return ApiResponse::new_not_found_error("Team has no results in this season");
+    }
+
+    ApiResponse::new_ok(
+        "Successfully fetched teammate battles",
+        TeamTeammates {
+            team,
+            season,
+            battles: teammates::teammate_battles(&results, query.bots),
+        },
+    )
+}
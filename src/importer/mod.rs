@@ -0,0 +1,387 @@
+mod session_file;
+
+use std::collections::HashMap;
+
+use serde::Serialize;
+use sqlx::{Pool, Postgres};
+
+use crate::models::db_objects::{CurrentSeat, DriverInfo, Position, RaceInfo, ResultImport, Team};
+use crate::utils::db;
+use crate::utils::metrics::Timed;
+
+pub use session_file::{parse, SessionEntry, SessionFormat, SessionStatus};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchedBy {
+    Username,
+    Alias,
+    FormerName,
+    //Not matched by name, the driver is the one of the seat given for it
+    Seat,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PreviewRow {
+    pub game_name: String,
+    pub matched_by: Option<MatchedBy>,
+    pub driver: Option<DriverInfo>,
+    pub team: Option<Team>,
+    pub seat_id: Option<i32>,
+    pub position: Position,
+    pub bot_result: bool,
+    pub pole: bool,
+    pub leading_lap: bool,
+    pub fastest_lap: bool,
+    pub qualy_result: Option<i32>,
+    pub race_time: Option<i32>,
+    pub gap_to_winner: Option<i32>,
+    pub laps_completed: Option<i32>,
+    pub best_lap_time: Option<i32>,
+    pub pit_stops: Option<i32>,
+}
+
+impl PreviewRow {
+    pub fn to_import(&self) -> Option<ResultImport> {
+        Some(ResultImport {
+            seat_id: self.seat_id?,
+            position: self.position,
+            bot_result: self.bot_result,
+            pole: self.pole,
+            leading_lap: self.leading_lap,
+            fastest_lap: self.fastest_lap,
+            qualy_result: self.qualy_result,
+            race_time: self.race_time,
+            gap_to_winner: self.gap_to_winner,
+            laps_completed: self.laps_completed,
+            best_lap_time: self.best_lap_time,
+            pit_stops: self.pit_stops,
+        })
+    }
+}
+
+//The mapped result sheet, only written to the database once it has no problems left and is confirmed.
+//Warnings point out guesses worth checking, but don't stop the import
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportPreview {
+    pub race: RaceInfo,
+    pub rows: Vec<PreviewRow>,
+    pub problems: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+impl ImportPreview {
+    pub fn is_complete(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    pub fn results(&self) -> Vec<ResultImport> {
+        self.rows.iter().filter_map(PreviewRow::to_import).collect()
+    }
+}
+
+//chosen_seats maps names from the session file onto seats, for drivers whose seat can't be derived
+pub async fn build_preview(
+    pool: &Pool<Postgres>,
+    race: RaceInfo,
+    entries: Vec<SessionEntry>,
+    chosen_seats: &HashMap<String, i32>,
+) -> Result<ImportPreview, sqlx::Error> {
+    let drivers = sqlx::query_as!(
+        DriverInfo,
+        "SELECT driver_id, username, driver_number, driver_image_url, country, birthday FROM driver"
+    )
+    .fetch_all(pool)
+    .timed("import_drivers")
+    .await?;
+    let aliases = sqlx::query!("SELECT alias, driver_id FROM import_alias")
+        .fetch_all(pool)
+        .timed("import_aliases")
+        .await?;
+    let former_names = sqlx::query!(
+        "SELECT username, driver_id FROM driver_alias
+        WHERE $1 BETWEEN first_season AND COALESCE(last_season, $1)",
+        race.season
+    )
+    .fetch_all(pool)
+    .timed("import_former_names")
+    .await?;
+
+    let by_id: HashMap<i32, &DriverInfo> = drivers.iter().map(|d| (d.driver_id, d)).collect();
+    let by_username: HashMap<String, &DriverInfo> = drivers
+        .iter()
+        .map(|d| (d.username.to_lowercase(), d))
+        .collect();
+    let by_alias: HashMap<String, &DriverInfo> = aliases
+        .iter()
+        .filter_map(|a| Some((a.alias.to_lowercase(), *by_id.get(&a.driver_id)?)))
+        .collect();
+    let by_former_name: HashMap<String, &DriverInfo> = former_names
+        .iter()
+        .filter_map(|a| Some((a.username.to_lowercase(), *by_id.get(&a.driver_id)?)))
+        .collect();
+
+    let mut problems = Vec::new();
+    let mut warnings = Vec::new();
+
+    //Keyed the same way names are matched
+    let chosen_seats: HashMap<String, i32> = chosen_seats
+        .iter()
+        .map(|(name, seat_id)| (name.trim().to_lowercase(), *seat_id))
+        .collect();
+    for name in chosen_seats.keys() {
+        if !entries.iter().any(|entry| entry.name.trim().to_lowercase() == *name) {
+            problems.push(format!("A seat was given for '{}', who is not in the session file", name));
+        }
+    }
+    let chosen_ids: Vec<i32> = chosen_seats.values().copied().collect();
+    let chosen = db::get_seats(pool, &chosen_ids).timed("get_seats").await?;
+    let chosen: HashMap<i32, CurrentSeat> = chosen.into_iter().map(|seat| (seat.seat_id, seat)).collect();
+
+    let matches: Vec<Option<(MatchedBy, &DriverInfo)>> = entries
+        .iter()
+        .map(|entry| {
+            let name = entry.name.trim().to_lowercase();
+            by_username
+                .get(&name)
+                .map(|d| (MatchedBy::Username, *d))
+                .or_else(|| by_alias.get(&name).map(|d| (MatchedBy::Alias, *d)))
+                .or_else(|| by_former_name.get(&name).map(|d| (MatchedBy::FormerName, *d)))
+                .or_else(|| {
+                    let seat = chosen.get(chosen_seats.get(&name)?)?;
+                    Some((MatchedBy::Seat, *by_id.get(&seat.driver_id)?))
+                })
+        })
+        .collect();
+
+    let driver_ids: Vec<i32> = matches.iter().flatten().map(|(_, d)| d.driver_id).collect();
+    let seats = db::get_current_seats(pool, race.season, &driver_ids)
+        .timed("get_current_seats")
+        .await?;
+    let seats: HashMap<i32, CurrentSeat> = seats.into_iter().map(|seat| (seat.driver_id, seat)).collect();
+
+    let fastest = entries.iter().filter_map(|entry| entry.best_lap).min();
+
+    let mut claimed: HashMap<i32, &str> = HashMap::new();
+    let mut rows = Vec::with_capacity(entries.len());
+    for (entry, matched) in entries.iter().zip(matches) {
+        let (matched_by, driver) = match matched {
+            Some((matched_by, driver)) => (Some(matched_by), Some(driver.clone())),
+            None => {
+                problems.push(format!("No driver or alias found for '{}'", entry.name));
+                (None, None)
+            }
+        };
+
+        let chosen_seat = match chosen_seats.get(&entry.name.trim().to_lowercase()) {
+            Some(seat_id) => {
+                let seat = chosen.get(seat_id);
+                if seat.is_none() {
+                    problems.push(format!("Seat {} given for '{}' does not exist", seat_id, entry.name));
+                }
+                seat
+            }
+            None => None,
+        };
+
+        let seat = match driver.as_ref() {
+            Some(driver) => {
+                if let Some(other) = claimed.insert(driver.driver_id, &entry.name) {
+                    problems.push(format!(
+                        "'{}' and '{}' both map to driver '{}'",
+                        other, entry.name, driver.username
+                    ));
+                }
+                match chosen_seat.or_else(|| seats.get(&driver.driver_id)) {
+                    Some(seat) if seat.driver_id != driver.driver_id => {
+                        problems.push(format!(
+                            "Seat {} given for '{}' belongs to another driver",
+                            seat.seat_id, entry.name
+                        ));
+                        None
+                    }
+                    Some(seat) => {
+                        //Their newest seat, which may be one from an earlier season
+                        if seat.assumed {
+                            warnings.push(format!(
+                                "Driver '{}' has no result in season {}, their latest seat with '{}' was assumed",
+                                driver.username, race.season, seat.name
+                            ));
+                        }
+                        Some(seat)
+                    }
+                    None => {
+                        problems.push(format!("Driver '{}' has no seat", driver.username));
+                        None
+                    }
+                }
+            }
+            None => None,
+        };
+        let seat_id = seat.map(|seat| seat.seat_id);
+        let team = seat.map(|seat| Team {
+            team_id: seat.team_id,
+            name: seat.name.clone(),
+            color: seat.color.clone(),
+        });
+
+        let position = match entry.status {
+            SessionStatus::Finished => Position::Finished(entry.position),
+            SessionStatus::Dnf => Position::Dnf,
+            SessionStatus::Dsq => Position::Dsq,
+            SessionStatus::Dns => Position::Dns,
+        };
+
+        rows.push(PreviewRow {
+            game_name: entry.name.clone(),
+            matched_by,
+            driver,
+            team,
+            seat_id,
+            position,
+            bot_result: entry.ai,
+            pole: entry.qualifying == Some(1),
+            leading_lap: entry.led_lap,
+            fastest_lap: entry
+                .fastest_lap
+                .unwrap_or(entry.best_lap.is_some() && entry.best_lap == fastest),
+            qualy_result: entry.qualifying,
+            race_time: entry.total_time,
+            gap_to_winner: entry.gap,
+            laps_completed: entry.laps,
+            best_lap_time: entry.best_lap,
+            pit_stops: entry.pit_stops,
+        });
+    }
+
+    Ok(ImportPreview {
+        race,
+        rows,
+        problems,
+        warnings,
+    })
+}
+
+pub async fn commit(pool: &Pool<Postgres>, preview: &ImportPreview) -> Result<usize, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let imported = db::import_race_results(&mut tx, &preview.race, &preview.results())
+        .timed("import_race_results")
+        .await?;
+    tx.commit().await?;
+    Ok(imported)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_db::seeded_pool;
+
+    fn race(race_id: i32, season: i32) -> RaceInfo {
+        RaceInfo {
+            race_name: format!("Race {race_id}"),
+            season,
+            race_id,
+            round: 1,
+        }
+    }
+
+    //bench1 raced as OldName in seasons 2 and 3
+    #[actix_web::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn former_names_only_match_in_their_seasons() {
+        let (_lock, pool) = seeded_pool().await;
+        sqlx::query!("INSERT INTO driver_alias (driver_id, username, first_season, last_season) VALUES (1, 'OldName', 2, 3)")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let entries = parse(SessionFormat::Csv, b"name,position\noldname,1\nbench2,2\n").unwrap();
+
+        let preview = build_preview(&pool, race(21, 2), entries.clone(), &HashMap::new()).await.unwrap();
+        assert_eq!(preview.rows[0].matched_by, Some(MatchedBy::FormerName));
+        assert_eq!(preview.rows[0].driver.as_ref().map(|driver| driver.driver_id), Some(1));
+        assert_eq!(preview.rows[1].matched_by, Some(MatchedBy::Username));
+        assert!(preview.is_complete(), "{:?}", preview.problems);
+
+        let preview = build_preview(&pool, race(61, 4), entries, &HashMap::new()).await.unwrap();
+        assert_eq!(preview.rows[0].matched_by, None);
+        assert_eq!(preview.problems, vec!["No driver or alias found for 'oldname'".to_string()]);
+
+        //Results are shown under the name of their season as well
+        let progression = db::get_progression_results(&pool, 3).await.unwrap();
+        let raced_as = progression.iter().find(|result| result.driver_id == 1).unwrap();
+        assert_eq!(raced_as.username, "OldName");
+        let progression = db::get_progression_results(&pool, 4).await.unwrap();
+        let raced_as = progression.iter().find(|result| result.driver_id == 1).unwrap();
+        assert_eq!(raced_as.username, "bench1");
+    }
+
+    //Nobody has raced in season 11 yet. bench2 has a seat that was never raced in, bench1 only seats from earlier seasons
+    #[actix_web::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn first_round_of_a_new_season_can_be_imported() {
+        let (_lock, pool) = seeded_pool().await;
+        sqlx::raw_sql(
+            "INSERT INTO seasons (season, season_name, finished, requires_recalc) VALUES (11, 'Season 11', false, false);
+            INSERT INTO races (race_id, race_name, season, round) VALUES (201, 'Opener', 11, 1);
+            INSERT INTO drives_in (seat_id, driver_id) VALUES (801, 2);
+            INSERT INTO drives_for (seat_id, team_id) VALUES (801, 5);",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let entries = parse(
+            SessionFormat::Csv,
+            b"name,position\nbench1,1\nbench2,2\nbench3,3\nnewcomer,4\n",
+        )
+        .unwrap();
+
+        //newcomer is bench4 under a name nobody knows yet, seat 121 is one of theirs
+        let seats = HashMap::from([("bench3".to_string(), 81), ("Newcomer".to_string(), 121)]);
+        let preview = build_preview(&pool, race(201, 11), entries.clone(), &seats).await.unwrap();
+        assert!(preview.is_complete(), "{:?}", preview.problems);
+        let chosen: Vec<(Option<MatchedBy>, Option<i32>)> =
+            preview.rows.iter().map(|row| (row.matched_by, row.seat_id)).collect();
+        assert_eq!(
+            chosen,
+            vec![
+                (Some(MatchedBy::Username), Some(40)),
+                (Some(MatchedBy::Username), Some(801)),
+                (Some(MatchedBy::Username), Some(81)),
+                (Some(MatchedBy::Seat), Some(121)),
+            ]
+        );
+        assert_eq!(
+            preview.warnings,
+            vec!["Driver 'bench1' has no result in season 11, their latest seat with 'Bench team 2' was assumed".to_string()]
+        );
+
+        assert_eq!(commit(&pool, &preview).await.unwrap(), 4);
+        let seat_ids = sqlx::query_scalar!(
+            "SELECT seat_id FROM has_result JOIN result ON result.result_id = has_result.result_id
+            WHERE race_id = 201 ORDER BY seat_id"
+        )
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        assert_eq!(seat_ids, vec![40, 81, 121, 801]);
+
+        //Seats have to exist and belong to the driver of the name
+        let seats = HashMap::from([
+            ("bench3".to_string(), 121),
+            ("bench1".to_string(), 9999),
+            ("ghost".to_string(), 1),
+        ]);
+        let preview = build_preview(&pool, race(201, 11), entries, &seats).await.unwrap();
+        let mut problems = preview.problems.clone();
+        problems.sort();
+        assert_eq!(
+            problems,
+            vec![
+                "A seat was given for 'ghost', who is not in the session file".to_string(),
+                "No driver or alias found for 'newcomer'".to_string(),
+                "Seat 121 given for 'bench3' belongs to another driver".to_string(),
+                "Seat 9999 given for 'bench1' does not exist".to_string(),
+            ]
+        );
+    }
+}
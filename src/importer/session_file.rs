@@ -0,0 +1,219 @@
+use serde::de::Visitor;
+use serde::{Deserialize, Deserializer};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionFormat {
+    Json,
+    Csv,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+pub enum SessionStatus {
+    //An empty CSV cell means the same as no status at all
+    #[default]
+    #[serde(alias = "finished", alias = "FINISHED", alias = "")]
+    Finished,
+    #[serde(rename = "DNF", alias = "dnf", alias = "Dnf", alias = "RET", alias = "Retired")]
+    Dnf,
+    #[serde(rename = "DSQ", alias = "dsq", alias = "Dsq", alias = "Disqualified")]
+    Dsq,
+    #[serde(rename = "DNS", alias = "dns", alias = "Dns")]
+    Dns,
+}
+
+//A single line of a session export, CSV headers use the same names as the JSON keys. Other columns, like the grid, are ignored
+#[derive(Debug, Clone, Deserialize)]
+pub struct SessionEntry {
+    pub name: String,
+    pub position: i32,
+    #[serde(default)]
+    pub status: SessionStatus,
+    #[serde(default)]
+    pub qualifying: Option<i32>,
+    #[serde(default, deserialize_with = "deserialize_time")]
+    pub total_time: Option<i32>,
+    #[serde(default, deserialize_with = "deserialize_time")]
+    pub gap: Option<i32>,
+    #[serde(default)]
+    pub laps: Option<i32>,
+    #[serde(default, deserialize_with = "deserialize_time")]
+    pub best_lap: Option<i32>,
+    #[serde(default)]
+    pub pit_stops: Option<i32>,
+    #[serde(default)]
+    pub led_lap: bool,
+    #[serde(default)]
+    pub fastest_lap: Option<bool>,
+    #[serde(default)]
+    pub ai: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonSession {
+    Wrapped { results: Vec<SessionEntry> },
+    Plain(Vec<SessionEntry>),
+}
+
+pub fn parse(format: SessionFormat, data: &[u8]) -> Result<Vec<SessionEntry>, String> {
+    let mut entries = match format {
+        SessionFormat::Json => match serde_json::from_slice::<JsonSession>(data) {
+            Ok(JsonSession::Wrapped { results }) | Ok(JsonSession::Plain(results)) => results,
+            Err(e) => return Err(format!("Invalid JSON session file: {e}")),
+        },
+        SessionFormat::Csv => {
+            let mut reader = csv::ReaderBuilder::new()
+                .trim(csv::Trim::All)
+                .from_reader(data);
+            reader
+                .deserialize()
+                .collect::<Result<Vec<SessionEntry>, _>>()
+                .map_err(|e| format!("Invalid CSV session file: {e}"))?
+        }
+    };
+
+    if entries.is_empty() {
+        return Err("Session file does not contain any results".into());
+    }
+    entries.sort_by_key(|entry| entry.position);
+    Ok(entries)
+}
+
+//Accepts whole milliseconds, fractional seconds, or a time string such as "1:23.456", "83.456" or "+1.234"
+fn deserialize_time<'de, D>(deserializer: D) -> Result<Option<i32>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct TimeVisitor;
+
+    impl<'de> Visitor<'de> for TimeVisitor {
+        type Value = Option<i32>;
+
+        fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+            formatter.write_str("a time in milliseconds or a lap time string")
+        }
+
+        fn visit_none<E: serde::de::Error>(self) -> Result<Self::Value, E> {
+            Ok(None)
+        }
+
+        fn visit_unit<E: serde::de::Error>(self) -> Result<Self::Value, E> {
+            Ok(None)
+        }
+
+        fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+            deserializer.deserialize_any(self)
+        }
+
+        fn visit_i64<E: serde::de::Error>(self, value: i64) -> Result<Self::Value, E> {
+            i32::try_from(value).map(Some).map_err(E::custom)
+        }
+
+        fn visit_u64<E: serde::de::Error>(self, value: u64) -> Result<Self::Value, E> {
+            i32::try_from(value).map(Some).map_err(E::custom)
+        }
+
+        fn visit_f64<E: serde::de::Error>(self, value: f64) -> Result<Self::Value, E> {
+            Ok(Some((value * 1000.0).round() as i32))
+        }
+
+        fn visit_str<E: serde::de::Error>(self, value: &str) -> Result<Self::Value, E> {
+            if value.trim().is_empty() {
+                return Ok(None);
+            }
+            parse_time(value)
+                .map(Some)
+                .ok_or_else(|| E::custom(format!("could not parse time '{value}'")))
+        }
+    }
+
+    deserializer.deserialize_option(TimeVisitor)
+}
+
+pub fn parse_time(text: &str) -> Option<i32> {
+    let text = text.trim().trim_start_matches('+');
+    let mut parts = text.rsplit(':');
+    let seconds: f64 = parts.next()?.parse().ok()?;
+    let minutes: f64 = match parts.next() {
+        Some(minutes) => minutes.parse().ok()?,
+        None => 0.0,
+    };
+    let hours: f64 = match parts.next() {
+        Some(hours) => hours.parse().ok()?,
+        None => 0.0,
+    };
+    if parts.next().is_some() || seconds < 0.0 {
+        return None;
+    }
+    Some(((hours * 3600.0 + minutes * 60.0 + seconds) * 1000.0).round() as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn times_parse_from_lap_and_gap_strings() {
+        assert_eq!(parse_time("1:23.456"), Some(83_456));
+        assert_eq!(parse_time("83.456"), Some(83_456));
+        assert_eq!(parse_time("+1.234"), Some(1_234));
+        assert_eq!(parse_time(" 1:02:03.5 "), Some(3_723_500));
+
+        assert_eq!(parse_time(""), None);
+        assert_eq!(parse_time("1 lap"), None);
+        assert_eq!(parse_time("-1.5"), None);
+        assert_eq!(parse_time("1:1:1:1"), None);
+    }
+
+    #[test]
+    fn csv_sessions_are_sorted_by_position() {
+        let data = b"name, position, status, qualifying, grid, total_time, gap, best_lap, led_lap, ai\n\
+            Bob, 2, , 1, 1, , +1.5, 1:21.000, false, false\n\
+            Alice, 1, Finished, 2, 2, 1:30:00.000, , 81.25, true, false\n\
+            Bot, 3, RET, , , , , , false, true\n";
+
+        let entries = parse(SessionFormat::Csv, data).unwrap();
+
+        let names: Vec<&str> = entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, vec!["Alice", "Bob", "Bot"]);
+        assert_eq!(entries[0].total_time, Some(5_400_000));
+        assert_eq!(entries[0].best_lap, Some(81_250));
+        assert!(entries[0].led_lap);
+        assert_eq!(entries[1].status, SessionStatus::Finished);
+        assert_eq!(entries[1].qualifying, Some(1));
+        assert_eq!(entries[1].gap, Some(1_500));
+        assert_eq!(entries[2].status, SessionStatus::Dnf);
+        assert_eq!(entries[2].best_lap, None);
+        assert!(entries[2].ai);
+    }
+
+    #[test]
+    fn json_sessions_may_be_wrapped() {
+        let plain = br#"[
+            {"name": "Bob", "position": 2, "status": "dsq", "best_lap": 81000},
+            {"name": "Alice", "position": 1, "total_time": "1:30:00.000", "gap": null, "fastest_lap": true}
+        ]"#;
+        let entries = parse(SessionFormat::Json, plain).unwrap();
+        assert_eq!(entries[0].name, "Alice");
+        assert_eq!(entries[0].total_time, Some(5_400_000));
+        assert_eq!(entries[0].gap, None);
+        assert_eq!(entries[0].fastest_lap, Some(true));
+        assert_eq!(entries[1].status, SessionStatus::Dsq);
+        assert_eq!(entries[1].best_lap, Some(81_000));
+
+        let wrapped = br#"{"track": "Monza", "results": [{"name": "Alice", "position": 1, "best_lap": 81.25}]}"#;
+        let entries = parse(SessionFormat::Json, wrapped).unwrap();
+        assert_eq!(entries[0].best_lap, Some(81_250));
+    }
+
+    #[test]
+    fn broken_or_empty_sessions_are_rejected() {
+        assert!(parse(SessionFormat::Json, b"[]").is_err());
+        assert!(parse(SessionFormat::Csv, b"name,position\n").is_err());
+        assert!(parse(SessionFormat::Json, br#"[{"name": "Alice"}]"#).is_err());
+
+        let error = parse(SessionFormat::Csv, b"name,position,best_lap\nAlice,1,fast\n").unwrap_err();
+        assert!(error.starts_with("Invalid CSV session file"), "{error}");
+    }
+}
@@ -0,0 +1,12 @@
+#![allow(unused_imports, dead_code)]
+
+pub mod backup;
+pub mod cli;
+pub mod config;
+pub mod exporter;
+pub mod handlers;
+pub mod importer;
+pub mod models;
+pub mod routes;
+pub mod telemetry;
+pub mod utils;
@@ -1,60 +1,182 @@
 #![allow(unused_imports, dead_code)]
 
+use actix_cors::Cors;
 use actix_web::{
+    http::header::{self, HeaderName},
+    middleware::{from_fn, Compress, Condition, DefaultHeaders},
     web::{self, route, Data},
     App, HttpServer,
 };
+use clap::Parser;
 use sqlx::{Pool, Postgres};
-use tokio::time::sleep;
-use tracing::{info, warn};
+use tracing::{error, info, warn};
 use tracing_actix_web::TracingLogger;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::fmt;
 
-mod handlers;
-mod models;
-mod routes;
-mod utils;
+use formula_destruction_backend::utils::request_id::REQUEST_ID_HEADER;
+use formula_destruction_backend::{backup, cli, config, exporter, handlers, importer, models, routes, telemetry, utils};
 
 #[actix_web::main]
 async fn main() {
-    set_logging();
-    info!("Starting server");
+    let cli = cli::Cli::parse();
 
     dotenv::dotenv().expect("Failed to read .env file");
+    set_logging(&config::LoggingConfig::from_env(), &config::TelemetryConfig::from_env());
+    let config = config::Config::from_env();
     let pool = configure_sql_connection().await;
     info!("Connected to database");
 
-    let status_pool = pool.clone();
-    tokio::spawn(async move {
-        let interval = std::time::Duration::from_secs(60);
-        loop{   
-            if let Err(e) = utils::db::update_season_results(&status_pool).await{
-                println!("Refresh failed: {}", e);
-            };
-            sleep(interval).await;
+    sqlx::migrate!()
+        .run(&pool)
+        .await
+        .expect("Failed to run database migrations");
+    info!("Database migrations applied");
+
+    let outcome = match cli.command.unwrap_or(cli::Command::Serve) {
+        cli::Command::Serve => {
+            run_server(pool, config).await;
+            Ok(())
         }
-    });
+        cli::Command::Import {
+            race,
+            format,
+            confirm,
+            seats,
+            file,
+        } => cli::import(&pool, race, format, confirm, seats, &file).await,
+        cli::Command::Export {
+            season,
+            format,
+            output,
+        } => cli::export(&pool, season, format, &output).await,
+        cli::Command::Backup { output } => cli::backup(&pool, &output).await,
+        cli::Command::Restore { file } => cli::restore(&pool, &file).await,
+    };
+
+    telemetry::shutdown().await;
+    if let Err(e) = outcome {
+        error!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+async fn run_server(pool: Pool<Postgres>, config: config::Config) {
+    info!("Starting server");
+
+    let cache = utils::cache::ResponseCache::new(config.cache.clone());
+    let (recalc, worker) = utils::recalculation::spawn(
+        pool.clone(),
+        config.recalc.clone(),
+        config.rating.clone(),
+        cache.clone(),
+    );
+    let limiter = utils::rate_limit::RateLimiter::new(config.rate_limit.clone(), pool.clone());
+    let pruning = limiter.spawn_pruning();
 
+    let app_pool = pool.clone();
+    let app_config = config.clone();
+    //Stops accepting connections on SIGINT or SIGTERM and gives in-flight requests the request timeout to finish
     HttpServer::new(move || {
+        let config = &app_config;
         App::new()
-            .app_data(Data::new(pool.clone()))
-            .wrap(TracingLogger::default())
+            .app_data(Data::new(app_pool.clone()))
+            .app_data(Data::new(recalc.clone()))
+            .app_data(Data::new(cache.clone()))
+            .app_data(Data::new(limiter.clone()))
+            .wrap(security_headers(&config.http))
+            .wrap(Condition::new(config.http.compression, Compress::default()))
+            .wrap(Condition::new(!config.http.cors_origins.is_empty(), cors(&config.http)))
+            .wrap(from_fn(utils::metrics::record_requests))
+            .wrap(from_fn(utils::request_id::echo_request_id))
+            .wrap(TracingLogger::<utils::request_id::RequestIdRootSpan>::new())
             .configure(routes::config)
     })
+    .shutdown_timeout(config.shutdown.requests.as_secs())
     .bind(("127.0.0.1", 8080))
     .expect("Failed to bind to address")
     .run()
     .await
     .expect("error running server");
+
+    info!("Server stopped, shutting down the recalculation worker");
+    worker.shutdown(config.shutdown.worker).await;
+    pruning.abort();
+
+    if tokio::time::timeout(config.shutdown.pool, pool.close()).await.is_err() {
+        warn!("Timed out waiting for database connections to close");
+    }
+    info!("Shutdown complete");
 }
 
-fn set_logging() {
-    let level = if cfg!(debug_assertions) {
-        tracing::Level::DEBUG
+fn cors(config: &config::HttpConfig) -> Cors {
+    let cors = if config.cors_origins.iter().any(|origin| origin == "*") {
+        Cors::default().allow_any_origin()
     } else {
-        tracing::Level::INFO
+        config
+            .cors_origins
+            .iter()
+            .fold(Cors::default(), |cors, origin| cors.allowed_origin(origin))
+    };
+    cors.allowed_methods(["GET", "POST"])
+        .allowed_headers([header::CONTENT_TYPE, header::AUTHORIZATION, header::IF_NONE_MATCH])
+        .allowed_header(REQUEST_ID_HEADER)
+        .expose_headers([header::ETAG, header::LAST_MODIFIED, header::RETRY_AFTER])
+        .expose_headers([REQUEST_ID_HEADER, HeaderName::from_static("x-cache")])
+        .max_age(config.cors_max_age)
+}
+
+//The API only serves JSON, so nothing it returns should be sniffed, framed or allowed to load other content
+fn security_headers(config: &config::HttpConfig) -> DefaultHeaders {
+    let headers = DefaultHeaders::new()
+        .add((header::X_CONTENT_TYPE_OPTIONS, "nosniff"))
+        .add((header::X_FRAME_OPTIONS, "DENY"))
+        .add((header::REFERRER_POLICY, "no-referrer"))
+        .add((header::CONTENT_SECURITY_POLICY, "default-src 'none'; frame-ancestors 'none'"));
+    match config.hsts_max_age {
+        Some(max_age) => headers.add((
+            header::STRICT_TRANSPORT_SECURITY,
+            format!("max-age={max_age}; includeSubDomains"),
+        )),
+        None => headers,
+    }
+}
+
+fn set_logging(config: &config::LoggingConfig, telemetry: &config::TelemetryConfig) {
+    let output = match config.format {
+        config::LogFormat::Text => fmt::layer().boxed(),
+        config::LogFormat::Json => fmt::layer().json().with_current_span(false).boxed(),
     };
 
-    tracing_subscriber::fmt().with_max_level(level).init();
+    let provider = telemetry
+        .endpoint
+        .as_deref()
+        .map(|endpoint| telemetry::tracer_provider(telemetry, endpoint));
+    let (export, export_error) = match provider {
+        Some(Ok(provider)) => {
+            let tracer = telemetry::install(provider);
+            (Some(tracing_opentelemetry::layer().with_tracer(tracer)), None)
+        }
+        Some(Err(e)) => (None, Some(e)),
+        None => (None, None),
+    };
+
+    tracing_subscriber::registry()
+        .with(LevelFilter::from_level(config.level))
+        .with(output)
+        .with(export)
+        .init();
+
+    for warning in config.warnings.iter().chain(&telemetry.warnings) {
+        warn!("{}", warning);
+    }
+
+    match (export_error, &telemetry.endpoint) {
+        (Some(e), _) => error!("Failed to set up span export, continuing without it: {}", e),
+        (None, Some(endpoint)) => info!(endpoint, "Exporting spans over OTLP"),
+        (None, None) => {}
+    }
 }
 
 async fn configure_sql_connection() -> Pool<Postgres> {
@@ -69,3 +191,70 @@ async fn configure_sql_connection() -> Pool<Postgres> {
 //Refactor DB interactionsm
 
 //cargo watch -x 'run' -c
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::Method;
+    use actix_web::{test, HttpResponse};
+
+    use super::*;
+
+    const ORIGIN: &str = "https://frontend.example";
+
+    #[actix_web::test]
+    async fn responses_carry_cors_compression_and_security_headers() {
+        let config = config::HttpConfig {
+            cors_origins: vec![ORIGIN.to_string()],
+            cors_max_age: 600,
+            compression: true,
+            hsts_max_age: Some(31_536_000),
+        };
+        //Wrapped the same way as in run_server
+        let app = test::init_service(
+            App::new()
+                .wrap(security_headers(&config))
+                .wrap(Condition::new(config.compression, Compress::default()))
+                .wrap(Condition::new(!config.cors_origins.is_empty(), cors(&config)))
+                .route(
+                    "/season/all",
+                    web::get().to(|| async { HttpResponse::Ok().json(vec!["season"; 500]) }),
+                ),
+        )
+        .await;
+
+        let preflight = test::TestRequest::default()
+            .method(Method::OPTIONS)
+            .uri("/season/all")
+            .insert_header((header::ORIGIN, ORIGIN))
+            .insert_header((header::ACCESS_CONTROL_REQUEST_METHOD, "GET"))
+            .insert_header((header::ACCESS_CONTROL_REQUEST_HEADERS, "if-none-match"))
+            .to_request();
+        let response = test::call_service(&app, preflight).await;
+        assert!(response.status().is_success());
+        let headers = response.headers();
+        assert_eq!(headers.get(header::ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(), ORIGIN);
+        assert_eq!(headers.get(header::ACCESS_CONTROL_MAX_AGE).unwrap(), "600");
+        let methods = headers.get(header::ACCESS_CONTROL_ALLOW_METHODS).unwrap().to_str().unwrap();
+        assert!(methods.contains("GET") && methods.contains("POST"));
+
+        let request = test::TestRequest::get()
+            .uri("/season/all")
+            .insert_header((header::ORIGIN, ORIGIN))
+            .insert_header((header::ACCEPT_ENCODING, "gzip"))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert!(response.status().is_success());
+        let headers = response.headers();
+        assert_eq!(headers.get(header::CONTENT_ENCODING).unwrap(), "gzip");
+        assert_eq!(headers.get(header::ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(), ORIGIN);
+        let exposed = headers.get(header::ACCESS_CONTROL_EXPOSE_HEADERS).unwrap().to_str().unwrap();
+        assert!(exposed.contains("etag") && exposed.contains("x-request-id"));
+        assert_eq!(headers.get(header::X_CONTENT_TYPE_OPTIONS).unwrap(), "nosniff");
+        assert_eq!(headers.get(header::X_FRAME_OPTIONS).unwrap(), "DENY");
+        assert_eq!(headers.get(header::REFERRER_POLICY).unwrap(), "no-referrer");
+        assert_eq!(
+            headers.get(header::STRICT_TRANSPORT_SECURITY).unwrap(),
+            "max-age=31536000; includeSubDomains"
+        );
+    }
+}
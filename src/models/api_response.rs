@@ -1,6 +1,8 @@
-use actix_web::{http::StatusCode, HttpResponse, Responder};
+use actix_web::{http::StatusCode, HttpMessage, HttpResponse, Responder};
 use serde::Serialize;
 
+use crate::utils::request_id::RequestId;
+
 #[derive(Serialize)]
 pub struct ApiResponse<T>
 where
@@ -11,11 +13,40 @@ where
     pub data: Option<T>,
 }
 
+//Errors carry the request id, so a failure reported by a client can be found in the logs
+#[derive(Serialize)]
+struct Body<T: Serialize> {
+    #[serde(flatten)]
+    response: ApiResponse<T>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+}
+
 impl<T: Serialize> Responder for ApiResponse<T> {
     type Body = actix_web::body::BoxBody;
 
-    fn respond_to(self, _req: &actix_web::HttpRequest) -> HttpResponse {
-        HttpResponse::build(StatusCode::from_u16(self.status_code).unwrap()).json(self)
+    fn respond_to(self, req: &actix_web::HttpRequest) -> HttpResponse {
+        let status = StatusCode::from_u16(self.status_code).unwrap();
+        let request_id = if status.is_client_error() || status.is_server_error() {
+            req.extensions().get::<RequestId>().map(|id| id.0.clone())
+        } else {
+            None
+        };
+        HttpResponse::build(status).json(Body {
+            response: self,
+            request_id,
+        })
+    }
+}
+
+impl<T: Serialize> ApiResponse<T> {
+    //Converts a response without data, e.g. an error, into a response for another payload type
+    pub fn into_error<U: Serialize>(self) -> ApiResponse<U> {
+        ApiResponse {
+            status_code: self.status_code,
+            message: self.message,
+            data: None,
+        }
     }
 }
 
@@ -48,6 +79,13 @@ impl ApiResponse<()> {
             data: None,
         }
     }
+    pub fn new_accepted<T: Serialize>(message: impl Into<String>) -> ApiResponse<T> {
+        ApiResponse {
+            status_code: 202,
+            message: message.into(),
+            data: None,
+        }
+    }
     pub fn new_bad_request<T: Serialize>(message: impl Into<String>) -> ApiResponse<T> {
         ApiResponse {
             status_code: 400,
@@ -55,6 +93,20 @@ impl ApiResponse<()> {
             data: None,
         }
     }
+    pub fn new_too_many_requests<T: Serialize>(message: impl Into<String>) -> ApiResponse<T> {
+        ApiResponse {
+            status_code: 429,
+            message: message.into(),
+            data: None,
+        }
+    }
+    pub fn new_service_unavailable<T: Serialize>(message: impl Into<String>, data: T) -> ApiResponse<T> {
+        ApiResponse {
+            status_code: 503,
+            message: message.into(),
+            data: Some(data),
+        }
+    }
     pub fn new_no_data_found<T: Serialize>(message: impl Into<String>) -> ApiResponse<T> {
         ApiResponse {
             status_code: 456,
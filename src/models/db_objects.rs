@@ -1,13 +1,18 @@
 
 
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
 use chrono::Utc;
-use serde::de::Deserialize;
+use serde::de::{Deserialize, Visitor};
 use serde::Deserializer;
 use serde::Serialize;
 use sqlx::prelude::FromRow;
 use sqlx::Column;
 use sqlx::Database;
 use sqlx::Decode;
+use sqlx::encode::IsNull;
 use sqlx::Postgres;
 use sqlx::Row;
 use sqlx::Type;
@@ -25,7 +30,9 @@ pub struct Driver {
     pub country : String,
     pub birthday :  Option<chrono::NaiveDate>,
     pub seats: Vec<Seat>,
-    pub season_results : Vec<SeasonResult>
+    pub season_results : Vec<SeasonResult>,
+    pub teammate_battles: Vec<TeammateBattle>,
+    pub bot_results: i32,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -70,10 +77,16 @@ pub struct RaceResult {
     pub season: i32,
     pub race_id: i32,
     pub race_name: String,
+    pub round: i32,
     pub points: i32,
+    pub race_time: Option<i32>,
+    pub gap_to_winner: Option<i32>,
+    pub laps_completed: Option<i32>,
+    pub best_lap_time: Option<i32>,
+    pub pit_stops: Option<i32>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Position {
     Finished(i32),
     Dnf,
@@ -90,6 +103,81 @@ impl Position {
             _ => Position::Finished(position),
         }
     }
+
+    //Finished positions first, then retirements, disqualifications and non starters
+    fn classification_key(&self) -> (u8, i32) {
+        match *self {
+            Position::Finished(pos) => (0, pos),
+            Position::Dnf => (1, 0),
+            Position::Dsq => (2, 0),
+            Position::Dns => (3, 0),
+        }
+    }
+
+    //Value stored in the database for this position
+    pub fn code(&self) -> i32 {
+        match *self {
+            Position::Finished(pos) => pos,
+            Position::Dnf => 101,
+            Position::Dsq => 111,
+            Position::Dns => 100,
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        matches!(self, Position::Finished(_))
+    }
+
+    //Alternative to the numeric serialization, use with #[serde(serialize_with = "Position::serialize_label")]
+    pub fn serialize_label<S>(position: &Position, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(position)
+    }
+}
+
+impl Ord for Position {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.classification_key().cmp(&other.classification_key())
+    }
+}
+
+impl PartialOrd for Position {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Position::Finished(pos) => write!(f, "P{pos}"),
+            Position::Dnf => f.write_str("DNF"),
+            Position::Dsq => f.write_str("DSQ"),
+            Position::Dns => f.write_str("DNS"),
+        }
+    }
+}
+
+//Accepts database codes ("101"), labels ("P3", "DNF") and plain positions ("3")
+impl FromStr for Position {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        match s.to_ascii_uppercase().as_str() {
+            "DNF" => return Ok(Position::Dnf),
+            "DSQ" => return Ok(Position::Dsq),
+            "DNS" => return Ok(Position::Dns),
+            _ => {}
+        }
+        let number = s.strip_prefix(['P', 'p']).unwrap_or(s);
+        match number.parse::<i32>() {
+            Ok(position) if position > 0 => Ok(Position::new(position)),
+            _ => Err(format!("could not map '{s}' to a position")),
+        }
+    }
 }
 
 impl Serialize for Position {
@@ -97,13 +185,7 @@ impl Serialize for Position {
     where
         S: Serializer,
     {
-        let value = match *self {
-            Position::Finished(pos) => pos,
-            Position::Dnf => 101,
-            Position::Dsq => 111,
-            Position::Dns => 100,
-        };
-        serializer.serialize_i32(value)
+        serializer.serialize_i32(self.code())
     }
 }
 
@@ -112,23 +194,38 @@ impl<'de> Deserialize<'de> for Position {
     where
         D: Deserializer<'de>,
     {
-        let s: String = String::deserialize(deserializer)?;
-        let s: i32 = s
-            .parse()
-            .map_err(|_| serde::de::Error::custom("could not map position to number"))?;
-        Ok(Self::new(s))
+        struct PositionVisitor;
+
+        impl<'de> Visitor<'de> for PositionVisitor {
+            type Value = Position;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a position number, code or label")
+            }
+
+            fn visit_i64<E: serde::de::Error>(self, value: i64) -> Result<Position, E> {
+                match i32::try_from(value) {
+                    Ok(position) if position > 0 => Ok(Position::new(position)),
+                    _ => Err(E::custom("could not map position to number")),
+                }
+            }
+
+            fn visit_u64<E: serde::de::Error>(self, value: u64) -> Result<Position, E> {
+                self.visit_i64(i64::try_from(value).map_err(E::custom)?)
+            }
+
+            fn visit_str<E: serde::de::Error>(self, value: &str) -> Result<Position, E> {
+                value.parse().map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_any(PositionVisitor)
     }
 }
 
 impl<'r> FromRow<'r, PgRow> for Position {
     fn from_row(row: &PgRow) -> sqlx::Result<Self> {
-        let value: Result<i32, sqlx::Error> = row.try_get("position");
-        match value {
-            Ok(value) => Ok(Position::Finished(value)),
-            Err(_) => Err(sqlx::Error::TypeNotFound {
-                type_name: "position".into(),
-            }),
-        }
+        row.try_get::<i32, _>("position").map(Position::new)
     }
 }
 
@@ -141,6 +238,17 @@ where i32: Decode<'r, DB>
     }
 }
 
+impl<'q, DB: Database> sqlx::Encode<'q, DB> for Position
+where i32: sqlx::Encode<'q, DB>
+{
+    fn encode_by_ref(
+        &self,
+        buf: &mut <DB as Database>::ArgumentBuffer<'q>,
+    ) -> Result<IsNull, sqlx::error::BoxDynError> {
+        <i32 as sqlx::Encode<DB>>::encode(self.code(), buf)
+    }
+}
+
 impl Type<Postgres> for Position{
     fn type_info() -> <Postgres as Database>::TypeInfo {
         <i32 as Type<Postgres>>::type_info()
@@ -153,6 +261,12 @@ impl From<i32> for Position {
     }
 }
 
+impl From<Position> for i32 {
+    fn from(value: Position) -> Self {
+        value.code()
+    }
+}
+
 #[derive(Debug, Clone, Serialize, FromRow)]
 pub struct Season {
     pub season: i32,
@@ -167,24 +281,692 @@ pub struct SeasonInfo{
 
 #[derive(Debug, Serialize, Clone)]
 pub struct Race{
+    pub race_id : i32,
     pub race_name : String,
+    pub round : i32,
     pub season : i32,
     pub results : Vec<PersonalResult>,
 }
 
+#[derive(Debug, Clone, Serialize)]
+pub struct RecalculationRun {
+    pub recalculation_id: i32,
+    pub season: i32,
+    pub started_at: chrono::DateTime<Utc>,
+    pub duration_ms: i32,
+    pub rows_written: i32,
+    pub outcome: String,
+    pub error: Option<String>,
+}
+
 #[derive(Debug, Serialize, FromRow, Clone)]
 pub struct RaceInfo{
     pub race_name : String,
     pub season : i32,
     pub race_id : i32,
+    pub round : i32,
 }
 
-#[derive(Debug, Serialize, FromRow, Clone)]
+#[derive(Debug, Serialize, Clone)]
 pub struct PersonalResult{
-    #[sqlx(flatten)]
     pub race_result : RaceResult,
-    #[sqlx(flatten)]
     pub driver_info : DriverInfo,
-    #[sqlx(flatten)]
     pub team : Team,
-}
\ No newline at end of file
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum QualifyingStage {
+    Q1,
+    Q2,
+    Q3,
+}
+
+impl QualifyingStage {
+    pub fn new(stage: i32) -> Self {
+        match stage {
+            3 => QualifyingStage::Q3,
+            2 => QualifyingStage::Q2,
+            _ => QualifyingStage::Q1,
+        }
+    }
+}
+
+impl Serialize for QualifyingStage {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let value = match *self {
+            QualifyingStage::Q1 => "Q1",
+            QualifyingStage::Q2 => "Q2",
+            QualifyingStage::Q3 => "Q3",
+        };
+        serializer.serialize_str(value)
+    }
+}
+
+impl From<i32> for QualifyingStage {
+    fn from(value: i32) -> Self {
+        QualifyingStage::new(value)
+    }
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct QualifyingResult {
+    pub position: i32,
+    pub grid_position: i32,
+    pub stage: QualifyingStage,
+    pub q1_time: Option<i32>,
+    pub q2_time: Option<i32>,
+    pub q3_time: Option<i32>,
+    pub best_time: Option<i32>,
+    pub gap_to_pole: Option<i32>,
+    pub interval: Option<i32>,
+    pub driver_info: DriverInfo,
+    pub team: Team,
+}
+
+impl QualifyingResult {
+    //Lap time set in the last stage the driver took part in, falling back to earlier stages
+    pub fn stage_time(&self) -> Option<i32> {
+        match self.stage {
+            QualifyingStage::Q3 => self.q3_time.or(self.q2_time).or(self.q1_time),
+            QualifyingStage::Q2 => self.q2_time.or(self.q1_time),
+            QualifyingStage::Q1 => self.q1_time,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct QualifyingClassification {
+    pub race: RaceInfo,
+    pub pole: Option<DriverInfo>,
+    pub results: Vec<QualifyingResult>,
+}
+
+impl QualifyingClassification {
+    //Results must be sorted by position, pole and gaps are derived from the classification
+    pub fn new(race: RaceInfo, mut results: Vec<QualifyingResult>) -> Self {
+        results.sort_by_key(|result| result.position);
+
+        let pole_time = results.first().and_then(|pole| pole.stage_time());
+        let mut previous_time = None;
+        for result in results.iter_mut() {
+            let time = result.stage_time();
+            result.best_time = [result.q1_time, result.q2_time, result.q3_time]
+                .into_iter()
+                .flatten()
+                .min();
+            result.gap_to_pole = time.zip(pole_time).map(|(time, pole)| time - pole);
+            result.interval = time.zip(previous_time).map(|(time, previous)| time - previous);
+            previous_time = time;
+        }
+
+        let pole = results.first().map(|pole| pole.driver_info.clone());
+        QualifyingClassification { race, pole, results }
+    }
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct ResultImport {
+    pub seat_id: i32,
+    pub position: Position,
+    #[serde(default)]
+    pub bot_result: bool,
+    #[serde(default)]
+    pub pole: bool,
+    #[serde(default)]
+    pub leading_lap: bool,
+    #[serde(default)]
+    pub fastest_lap: bool,
+    pub qualy_result: Option<i32>,
+    pub race_time: Option<i32>,
+    pub gap_to_winner: Option<i32>,
+    pub laps_completed: Option<i32>,
+    pub best_lap_time: Option<i32>,
+    pub pit_stops: Option<i32>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct HeadToHead {
+    pub driver: DriverInfo,
+    pub opponent: DriverInfo,
+    pub driver_ahead: i32,
+    pub opponent_ahead: i32,
+    //Races where either result was driven by the AI, only reported with bots=flag
+    pub bot_races: Option<i32>,
+    pub races: Vec<HeadToHeadRace>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct HeadToHeadRace {
+    pub race_id: i32,
+    pub race_name: String,
+    pub round: i32,
+    pub season: i32,
+    pub driver_position: Position,
+    pub opponent_position: Position,
+    pub driver_bot_result: bool,
+    pub opponent_bot_result: bool,
+    pub driver_race_time: Option<i32>,
+    pub opponent_race_time: Option<i32>,
+    pub time_gap: Option<i32>,
+    pub driver_best_lap_time: Option<i32>,
+    pub opponent_best_lap_time: Option<i32>,
+}
+
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct DriverSeat {
+    pub driver_id: i32,
+    pub seat_id: i32,
+    pub team_id: i32,
+    pub name: String,
+    pub color: Option<String>,
+}
+
+//The seat a driver's results in a season are attached to
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct CurrentSeat {
+    pub driver_id: i32,
+    pub seat_id: i32,
+    pub team_id: i32,
+    pub name: String,
+    pub color: Option<String>,
+    //True when the seat has results, but none in the season. It is then only the driver's most recent one
+    pub assumed: bool,
+}
+
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct DriverAlias {
+    pub username: String,
+    pub first_season: i32,
+    pub last_season: Option<i32>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DriverSearchResult {
+    pub driver_info: DriverInfo,
+    pub matched_alias: Option<DriverAlias>,
+}
+
+//Flat result line used by the CSV and JSON exports
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct ExportResult {
+    pub result_id: i32,
+    pub season: i32,
+    pub season_name: String,
+    pub race_id: i32,
+    pub race_name: String,
+    pub round: i32,
+    pub driver_id: i32,
+    pub username: String,
+    pub raced_as: String,
+    pub team_id: i32,
+    pub team_name: String,
+    #[serde(serialize_with = "Position::serialize_label")]
+    pub position: Position,
+    pub points: i32,
+    pub bot_result: bool,
+    pub pole: bool,
+    pub leading_lap: bool,
+    pub fastest_lap: bool,
+    pub qualy_result: Option<i32>,
+    pub race_time: Option<i32>,
+    pub gap_to_winner: Option<i32>,
+    pub laps_completed: Option<i32>,
+    pub best_lap_time: Option<i32>,
+    pub pit_stops: Option<i32>,
+}
+
+impl ExportResult {
+    //Column names of the CSV export, in field order, so the header can be written before the first row
+    pub const FIELDS: [&'static str; 23] = [
+        "result_id",
+        "season",
+        "season_name",
+        "race_id",
+        "race_name",
+        "round",
+        "driver_id",
+        "username",
+        "raced_as",
+        "team_id",
+        "team_name",
+        "position",
+        "points",
+        "bot_result",
+        "pole",
+        "leading_lap",
+        "fastest_lap",
+        "qualy_result",
+        "race_time",
+        "gap_to_winner",
+        "laps_completed",
+        "best_lap_time",
+        "pit_stops",
+    ];
+}
+
+//How results driven by the AI on a driver's behalf are treated, set with ?bots=include|exclude|flag
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BotPolicy {
+    //Counted like any other result
+    #[default]
+    Include,
+    //Left out before anything is computed
+    Exclude,
+    //Counted, with the part that came from bot results reported next to the totals
+    Flag,
+}
+
+impl BotPolicy {
+    pub fn keeps(self, bot_result: bool) -> bool {
+        !(bot_result && self == BotPolicy::Exclude)
+    }
+
+    pub fn flags(self) -> bool {
+        self == BotPolicy::Flag
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, serde::Deserialize)]
+pub struct BotQuery {
+    #[serde(default)]
+    pub bots: BotPolicy,
+}
+
+//A scored result as input for the standings progression
+#[derive(Debug, Clone)]
+pub struct ProgressionResult {
+    pub race_id: i32,
+    pub race_name: String,
+    pub round: i32,
+    pub driver_id: i32,
+    pub username: String,
+    pub team: Team,
+    pub position: Position,
+    pub points: i32,
+    pub bot_result: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SeasonProgression {
+    pub season: i32,
+    pub rounds: Vec<RoundStandings>,
+}
+
+//Championship standings as they were after a round
+#[derive(Debug, Clone, Serialize)]
+pub struct RoundStandings {
+    pub race_id: i32,
+    pub race_name: String,
+    pub round: i32,
+    pub drivers: Vec<DriverStanding>,
+    pub teams: Vec<TeamStanding>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DriverStanding {
+    pub position: i32,
+    pub driver_id: i32,
+    pub username: String,
+    pub team: Team,
+    pub points: i32,
+    pub total_points: i32,
+    pub bots: Option<BotShare>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TeamStanding {
+    pub position: i32,
+    pub team: Team,
+    pub points: i32,
+    pub total_points: i32,
+    pub bots: Option<BotShare>,
+}
+
+//Part of a total that came from bot results, only reported with bots=flag
+#[derive(Debug, Clone, Copy, Default, Serialize, PartialEq, Eq)]
+pub struct BotShare {
+    pub results: i32,
+    pub points: i32,
+}
+
+//One line of a season's points scheme
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct PointsEntry {
+    pub position: i32,
+    pub pole: bool,
+    pub leading_lap: bool,
+    pub fastest_lap: bool,
+    pub points: i32,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct HypotheticalRace {
+    pub race_id: i32,
+    pub results: Vec<HypotheticalResult>,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct HypotheticalResult {
+    pub driver_id: i32,
+    //The seat to score the result for, derived like an import when left out
+    #[serde(default)]
+    pub seat_id: Option<i32>,
+    pub position: Position,
+    #[serde(default)]
+    pub bot_result: bool,
+    #[serde(default)]
+    pub pole: bool,
+    #[serde(default)]
+    pub leading_lap: bool,
+    #[serde(default)]
+    pub fastest_lap: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SeasonScenarios {
+    pub season: i32,
+    pub remaining_races: Vec<RaceInfo>,
+    pub hypothetical_races: Vec<i32>,
+    pub max_driver_points_per_race: i32,
+    pub drivers: Vec<DriverScenario>,
+    pub teams: Vec<TeamScenario>,
+    pub driver_clinch: Option<ClinchCondition>,
+    pub team_clinch: Option<ClinchCondition>,
+    //Seats of hypothetical results that were assumed rather than known
+    pub warnings: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DriverScenario {
+    pub position: i32,
+    pub driver_id: i32,
+    pub username: String,
+    pub team: Team,
+    pub points: i32,
+    pub max_points: i32,
+    pub in_contention: bool,
+    pub clinched: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TeamScenario {
+    pub position: i32,
+    pub team: Team,
+    pub points: i32,
+    pub max_points: i32,
+    pub in_contention: bool,
+    pub clinched: bool,
+}
+
+//A classified result as input for the rating engine, in the order the races were run
+#[derive(Debug, Clone)]
+pub struct RatingInput {
+    pub race_id: i32,
+    pub driver_id: i32,
+    pub position: Position,
+    pub bot_result: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RatingChange {
+    pub race_id: i32,
+    pub driver_id: i32,
+    pub rating_before: f64,
+    pub rating_after: f64,
+    pub bot_result: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RatingHistoryEntry {
+    pub race_id: i32,
+    pub race_name: String,
+    pub season: i32,
+    pub round: i32,
+    pub rating_before: f64,
+    pub rating_after: f64,
+    pub bot_result: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DriverRating {
+    pub rank: i32,
+    pub driver_info: DriverInfo,
+    pub rating: f64,
+    pub races: i64,
+    //Rated races the driver's result was driven by the AI, only reported with bots=flag
+    pub bot_races: Option<i64>,
+}
+
+//A result of a driver for their team, input for the teammate comparisons
+#[derive(Debug, Clone)]
+pub struct TeammateResult {
+    pub season: i32,
+    pub team_id: i32,
+    pub race_id: i32,
+    pub driver_id: i32,
+    pub username: String,
+    pub position: Position,
+    pub qualifying: Option<i32>,
+    pub points: i32,
+    pub bot_result: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, PartialEq, Eq)]
+pub struct HeadToHeadScore {
+    pub driver: i32,
+    pub teammate: i32,
+}
+
+//Two drivers of the same team in a season, compared over the races they both drove
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct TeammateBattle {
+    pub season: i32,
+    pub team_id: i32,
+    pub driver_id: i32,
+    pub driver_username: String,
+    pub teammate_id: i32,
+    pub teammate_username: String,
+    pub races: i32,
+    pub qualifying: HeadToHeadScore,
+    //Only races both of them finished
+    pub race: HeadToHeadScore,
+    pub driver_points: i32,
+    pub teammate_points: i32,
+    pub driver_points_share: Option<f64>,
+    //Positive when the driver finished ahead, only races both of them finished
+    pub average_position_gap: Option<f64>,
+    //Shared races where either result was driven by the AI, only reported with bots=flag
+    pub bot_races: Option<i32>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TeamTeammates {
+    pub team: Team,
+    pub season: i32,
+    pub battles: Vec<TeammateBattle>,
+}
+
+//What the championship leader needs against the closest challenger, ids are driver or team ids
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct ClinchCondition {
+    pub leader_id: i32,
+    pub rival_id: Option<i32>,
+    pub lead: i32,
+    pub clinched: bool,
+    //Points that clinch the title whatever the rival scores
+    pub points_needed: i32,
+    //Lead over the rival after the next race that settles it
+    pub lead_needed_after_next_race: Option<i32>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Readiness {
+    pub database: bool,
+    //Migrations embedded in this build that the database has not applied yet
+    pub pending_migrations: Vec<i64>,
+    pub recalculation_worker: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VersionInfo {
+    pub version: String,
+    pub git_hash: String,
+    pub schema_version: Option<i64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn positions_order_by_classification() {
+        let mut positions = vec![
+            Position::Dns,
+            Position::Finished(10),
+            Position::Dsq,
+            Position::Dnf,
+            Position::Finished(2),
+        ];
+        positions.sort();
+
+        assert_eq!(
+            positions,
+            vec![
+                Position::Finished(2),
+                Position::Finished(10),
+                Position::Dnf,
+                Position::Dsq,
+                Position::Dns,
+            ]
+        );
+    }
+
+    #[test]
+    fn positions_round_trip_through_codes_and_labels() {
+        for code in [1, 20, 100, 101, 111] {
+            let position = Position::new(code);
+            assert_eq!(position.code(), code);
+            assert_eq!(position.to_string().parse::<Position>(), Ok(position));
+        }
+        assert_eq!(Position::Finished(3).to_string(), "P3");
+        assert_eq!("dnf".parse::<Position>(), Ok(Position::Dnf));
+        assert!("P0".parse::<Position>().is_err());
+    }
+
+    #[test]
+    fn positions_deserialize_from_numbers_and_strings() {
+        let positions: Vec<Position> = serde_json::from_str(r#"[3, "4", "P5", "DSQ", 101]"#).unwrap();
+
+        assert_eq!(
+            positions,
+            vec![
+                Position::Finished(3),
+                Position::Finished(4),
+                Position::Finished(5),
+                Position::Dsq,
+                Position::Dnf,
+            ]
+        );
+        assert_eq!(serde_json::to_string(&Position::Dnf).unwrap(), "101");
+    }
+
+    fn qualifying_result(
+        position: i32,
+        stage: i32,
+        q1_time: Option<i32>,
+        q2_time: Option<i32>,
+        q3_time: Option<i32>,
+    ) -> QualifyingResult {
+        QualifyingResult {
+            position,
+            grid_position: position,
+            stage: QualifyingStage::new(stage),
+            q1_time,
+            q2_time,
+            q3_time,
+            best_time: None,
+            gap_to_pole: None,
+            interval: None,
+            driver_info: DriverInfo {
+                driver_id: position,
+                username: format!("driver {position}"),
+                driver_number: position,
+                driver_image_url: String::new(),
+                country: String::new(),
+                birthday: None,
+            },
+            team: Team {
+                team_id: 1,
+                name: "Team".to_string(),
+                color: None,
+            },
+        }
+    }
+
+    #[test]
+    fn qualifying_stages_fall_back_to_earlier_times() {
+        assert_eq!(QualifyingStage::new(3), QualifyingStage::Q3);
+        assert_eq!(QualifyingStage::new(2), QualifyingStage::Q2);
+        assert_eq!(QualifyingStage::new(0), QualifyingStage::Q1);
+
+        assert_eq!(qualifying_result(1, 3, Some(90_500), Some(90_200), Some(90_000)).stage_time(), Some(90_000));
+        //No time in Q3, the Q2 lap stands
+        assert_eq!(qualifying_result(9, 3, Some(91_000), Some(90_800), None).stage_time(), Some(90_800));
+        //A Q1 exit is classified on the Q1 time, even with a faster lap recorded for a later stage
+        assert_eq!(qualifying_result(18, 1, Some(91_500), Some(90_000), None).stage_time(), Some(91_500));
+    }
+
+    #[test]
+    fn qualifying_classification_derives_pole_gaps_and_intervals() {
+        let race = RaceInfo {
+            race_name: "Monza".to_string(),
+            season: 1,
+            race_id: 1,
+            round: 1,
+        };
+        //Handed over out of order, the classification sorts by position
+        let results = vec![
+            qualifying_result(3, 2, Some(91_200), Some(90_900), None),
+            qualifying_result(1, 3, Some(90_800), Some(90_600), Some(90_100)),
+            qualifying_result(4, 1, None, None, None),
+            qualifying_result(2, 3, Some(90_700), Some(90_400), Some(90_350)),
+        ];
+
+        let classification = QualifyingClassification::new(race, results);
+
+        assert_eq!(classification.pole.as_ref().map(|pole| pole.driver_id), Some(1));
+        let positions: Vec<i32> = classification.results.iter().map(|result| result.position).collect();
+        assert_eq!(positions, vec![1, 2, 3, 4]);
+
+        let pole = &classification.results[0];
+        assert_eq!((pole.best_time, pole.gap_to_pole, pole.interval), (Some(90_100), Some(0), None));
+
+        let second = &classification.results[1];
+        assert_eq!((second.best_time, second.gap_to_pole, second.interval), (Some(90_350), Some(250), Some(250)));
+
+        //Out in Q2, compared on the Q2 time
+        let third = &classification.results[2];
+        assert_eq!((third.best_time, third.gap_to_pole, third.interval), (Some(90_900), Some(800), Some(550)));
+
+        //Without a time there is nothing to compare
+        let fourth = &classification.results[3];
+        assert_eq!((fourth.best_time, fourth.gap_to_pole, fourth.interval), (None, None, None));
+    }
+
+    #[test]
+    fn empty_qualifying_has_no_pole() {
+        let race = RaceInfo {
+            race_name: "Monza".to_string(),
+            season: 1,
+            race_id: 1,
+            round: 1,
+        };
+        let classification = QualifyingClassification::new(race, Vec::new());
+        assert!(classification.pole.is_none());
+        assert!(classification.results.is_empty());
+    }
+}
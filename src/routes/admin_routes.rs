@@ -0,0 +1,6 @@
+use actix_web::web;
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.configure(crate::handlers::admin::config);
+    cfg.configure(crate::handlers::races::admin_config);
+}
@@ -0,0 +1,5 @@
+use actix_web::web;
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.configure(crate::handlers::export::config);
+}
@@ -1,11 +1,51 @@
+mod admin_routes;
 mod driver_routes;
+mod export_routes;
+mod monitoring_routes;
+mod race_routes;
 mod season_routes;
 mod team_routes;
 
+use actix_web::middleware::from_fn;
 use actix_web::web;
 
+use crate::utils::cache::cache_responses;
+use crate::utils::rate_limit::{rate_limit, RouteGroup};
+
+//Limited before the cache, a cached response is cheap but still counts against the client
 pub fn config(cfg: &mut web::ServiceConfig) {
-    cfg.service(web::scope("/driver").configure(driver_routes::config));
-    cfg.service(web::scope("/season").configure(season_routes::config));
-    cfg.service(web::scope("/team").configure(team_routes::config));
+    cfg.service(
+        web::scope("/driver")
+            .wrap(from_fn(cache_responses))
+            .wrap(from_fn(|req, next| rate_limit(RouteGroup::Driver, req, next)))
+            .configure(driver_routes::config),
+    );
+    cfg.service(
+        web::scope("/season")
+            .wrap(from_fn(cache_responses))
+            .wrap(from_fn(|req, next| rate_limit(RouteGroup::Season, req, next)))
+            .configure(season_routes::config),
+    );
+    cfg.service(
+        web::scope("/team")
+            .wrap(from_fn(cache_responses))
+            .wrap(from_fn(|req, next| rate_limit(RouteGroup::Team, req, next)))
+            .configure(team_routes::config),
+    );
+    cfg.service(
+        web::scope("/race")
+            .wrap(from_fn(|req, next| rate_limit(RouteGroup::Race, req, next)))
+            .configure(race_routes::config),
+    );
+    cfg.service(
+        web::scope("/export")
+            .wrap(from_fn(|req, next| rate_limit(RouteGroup::Export, req, next)))
+            .configure(export_routes::config),
+    );
+    cfg.service(
+        web::scope("/admin")
+            .wrap(from_fn(|req, next| rate_limit(RouteGroup::Admin, req, next)))
+            .configure(admin_routes::config),
+    );
+    cfg.configure(monitoring_routes::config);
 }
@@ -0,0 +1,117 @@
+use opentelemetry::trace::{TraceError, TracerProvider as _};
+use opentelemetry::{global, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{self, Sampler, Tracer, TracerProvider};
+use opentelemetry_sdk::{runtime, Resource};
+
+use crate::config::TelemetryConfig;
+
+//Batches spans and sends them to the collector over OTLP/gRPC.
+//The exporter runs on its own thread, actix runs a single threaded runtime that would block on flushing at shutdown
+pub fn tracer_provider(config: &TelemetryConfig, endpoint: &str) -> Result<TracerProvider, TraceError> {
+    let exporter = opentelemetry_otlp::new_exporter()
+        .tonic()
+        .with_endpoint(endpoint)
+        .build_span_exporter()?;
+
+    let sampler = Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(config.sample_ratio)));
+    Ok(TracerProvider::builder()
+        .with_batch_exporter(exporter, runtime::TokioCurrentThread)
+        .with_config(
+            trace::config()
+                .with_sampler(sampler)
+                .with_resource(Resource::new([KeyValue::new(
+                    "service.name",
+                    config.service_name.clone(),
+                )])),
+        )
+        .build())
+}
+
+//Makes the provider global, so incoming traceparent headers are picked up and spans are flushed at shutdown
+pub fn install(provider: TracerProvider) -> Tracer {
+    let tracer = provider.tracer(env!("CARGO_PKG_NAME"));
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    global::set_tracer_provider(provider);
+    tracer
+}
+
+//Exports the spans that are still buffered. Blocks until that is done,
+//so it runs off the runtime thread that drives the connection to the collector
+pub async fn shutdown() {
+    let _ = tokio::task::spawn_blocking(global::shutdown_tracer_provider).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::time::Duration;
+
+    use opentelemetry_proto::tonic::collector::trace::v1::trace_service_server::{
+        TraceService, TraceServiceServer,
+    };
+    use opentelemetry_proto::tonic::collector::trace::v1::{
+        ExportTraceServiceRequest, ExportTraceServiceResponse,
+    };
+    use tokio::sync::mpsc;
+    use tracing_subscriber::prelude::*;
+
+    use super::*;
+
+    //Stands in for a collector, passing on the names of the spans it receives
+    struct Collector(mpsc::UnboundedSender<String>);
+
+    #[tonic::async_trait]
+    impl TraceService for Collector {
+        async fn export(
+            &self,
+            request: tonic::Request<ExportTraceServiceRequest>,
+        ) -> Result<tonic::Response<ExportTraceServiceResponse>, tonic::Status> {
+            let spans = request
+                .into_inner()
+                .resource_spans
+                .into_iter()
+                .flat_map(|resource| resource.scope_spans)
+                .flat_map(|scope| scope.spans);
+            for span in spans {
+                let _ = self.0.send(span.name);
+            }
+            Ok(tonic::Response::new(ExportTraceServiceResponse { partial_success: None }))
+        }
+    }
+
+    #[actix_web::test]
+    async fn exports_spans_to_the_collector() {
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let (sender, mut received) = mpsc::unbounded_channel();
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(TraceServiceServer::new(Collector(sender)))
+                .serve(([127, 0, 0, 1], port).into()),
+        );
+
+        let config = TelemetryConfig {
+            endpoint: None,
+            service_name: "test".to_string(),
+            sample_ratio: 1.0,
+            warnings: Vec::new(),
+        };
+        let provider = tracer_provider(&config, &format!("http://127.0.0.1:{port}")).unwrap();
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+        tracing::subscriber::with_default(subscriber, || {
+            let _job = tracing::info_span!("recalculation").entered();
+            let _query = tracing::info_span!("db_query", db.query = "get_rating_inputs").entered();
+        });
+        tokio::task::spawn_blocking(move || provider.force_flush()).await.unwrap();
+
+        let mut names = Vec::new();
+        while names.len() < 2 {
+            let name = tokio::time::timeout(Duration::from_secs(5), received.recv()).await;
+            names.push(name.expect("collector received no spans").unwrap());
+        }
+        names.sort();
+        assert_eq!(names, vec!["db_query", "recalculation"]);
+    }
+}
@@ -0,0 +1,300 @@
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use actix_web::body::{self, BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{
+    self, CacheControl, CacheDirective, EntityTag, ETag, HeaderValue, HttpDate, IfModifiedSince,
+    IfNoneMatch, LastModified,
+};
+use actix_web::http::{Method, StatusCode};
+use actix_web::middleware::Next;
+use actix_web::web::{self, Bytes};
+use actix_web::{Error, HttpMessage, HttpRequest, HttpResponse};
+use tracing::debug;
+
+use crate::config::CacheConfig;
+use crate::utils::metrics::METRICS;
+
+#[derive(Debug, Clone)]
+struct Entry {
+    body: Bytes,
+    content_type: Option<HeaderValue>,
+    etag: EntityTag,
+    last_modified: SystemTime,
+}
+
+#[derive(Debug)]
+struct State {
+    //Bumped on every invalidation, responses computed against an older generation are not stored
+    generation: u64,
+    last_modified: SystemTime,
+    entries: HashMap<String, Entry>,
+}
+
+//In-process cache of successful GET responses, cleared as a whole whenever the underlying data changes
+#[derive(Debug, Clone)]
+pub struct ResponseCache {
+    config: CacheConfig,
+    state: Arc<RwLock<State>>,
+}
+
+//HTTP dates only have second precision
+fn whole_seconds(time: SystemTime) -> SystemTime {
+    let seconds = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    UNIX_EPOCH + Duration::from_secs(seconds)
+}
+
+impl ResponseCache {
+    pub fn new(config: CacheConfig) -> Self {
+        ResponseCache {
+            config,
+            state: Arc::new(RwLock::new(State {
+                generation: 0,
+                last_modified: whole_seconds(SystemTime::now()),
+                entries: HashMap::new(),
+            })),
+        }
+    }
+
+    pub fn invalidate(&self) {
+        let mut state = self.state.write().unwrap();
+        state.generation += 1;
+        state.last_modified = whole_seconds(SystemTime::now());
+        if !state.entries.is_empty() {
+            debug!(entries = state.entries.len(), "Response cache invalidated");
+            state.entries.clear();
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<Entry> {
+        self.state.read().unwrap().entries.get(key).cloned()
+    }
+
+    fn version(&self) -> (u64, SystemTime) {
+        let state = self.state.read().unwrap();
+        (state.generation, state.last_modified)
+    }
+
+    fn insert(&self, key: String, generation: u64, entry: Entry) {
+        let mut state = self.state.write().unwrap();
+        if state.generation != generation {
+            return;
+        }
+        if state.entries.len() >= self.config.max_entries {
+            state.entries.clear();
+        }
+        state.entries.insert(key, entry);
+    }
+}
+
+fn not_modified(req: &HttpRequest, entry: &Entry) -> bool {
+    //If-None-Match takes precedence, If-Modified-Since is only looked at without it
+    match req.get_header::<IfNoneMatch>() {
+        Some(IfNoneMatch::Any) => true,
+        Some(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(&entry.etag)),
+        None => req
+            .get_header::<IfModifiedSince>()
+            .is_some_and(|since| entry.last_modified <= SystemTime::from(since.0)),
+    }
+}
+
+fn respond(req: &HttpRequest, entry: &Entry, hit: bool) -> HttpResponse {
+    let not_modified = not_modified(req, entry);
+    let mut response = if not_modified {
+        HttpResponse::NotModified()
+    } else {
+        HttpResponse::Ok()
+    };
+    response
+        .insert_header(ETag(entry.etag.clone()))
+        .insert_header(LastModified(HttpDate::from(entry.last_modified)))
+        .insert_header(CacheControl(vec![CacheDirective::Public, CacheDirective::NoCache]))
+        .insert_header(("X-Cache", if hit { "HIT" } else { "MISS" }));
+
+    if not_modified {
+        return response.finish();
+    }
+    if let Some(content_type) = &entry.content_type {
+        response.insert_header((header::CONTENT_TYPE, content_type.clone()));
+    }
+    response.body(entry.body.clone())
+}
+
+//Serves GET requests from the cache and answers revalidation with 304 Not Modified
+pub async fn cache_responses(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let cache = match req.app_data::<web::Data<ResponseCache>>() {
+        Some(cache) if cache.config.enabled && req.method() == Method::GET => cache.clone(),
+        _ => return next.call(req).await.map(ServiceResponse::map_into_boxed_body),
+    };
+
+    let key = req.uri().to_string();
+    let cached = cache.get(&key);
+    METRICS.record_cache(cached.is_some());
+    if let Some(entry) = cached {
+        let response = respond(req.request(), &entry, true);
+        return Ok(req.into_response(response));
+    }
+
+    let (generation, last_modified) = cache.version();
+    let response = next.call(req).await?;
+    if response.status() != StatusCode::OK {
+        return Ok(response.map_into_boxed_body());
+    }
+
+    let (req, response) = response.into_parts();
+    let content_type = response.headers().get(header::CONTENT_TYPE).cloned();
+    let body = body::to_bytes(response.into_body())
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to read response body"))?;
+
+    //Weak, the body is compressed on the way out and the same tag then stands for differently encoded bytes
+    let mut hasher = DefaultHasher::new();
+    body.hash(&mut hasher);
+    let entry = Entry {
+        body,
+        content_type,
+        etag: EntityTag::new_weak(format!("{:016x}", hasher.finish())),
+        last_modified,
+    };
+    cache.insert(key, generation, entry.clone());
+
+    let response = respond(&req, &entry, false);
+    Ok(ServiceResponse::new(req, response))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use actix_web::middleware::from_fn;
+    use actix_web::{test, App};
+
+    use super::*;
+
+    fn cache() -> ResponseCache {
+        ResponseCache::new(CacheConfig {
+            enabled: true,
+            max_entries: 16,
+        })
+    }
+
+    //Counts how often the handler behind the cache ran
+    async fn seasons(calls: web::Data<AtomicUsize>) -> HttpResponse {
+        calls.fetch_add(1, Ordering::SeqCst);
+        HttpResponse::Ok().json(["season 1", "season 2"])
+    }
+
+    fn x_cache(response: &ServiceResponse) -> &str {
+        response.headers().get("X-Cache").unwrap().to_str().unwrap()
+    }
+
+    #[actix_web::test]
+    async fn second_request_is_served_from_the_cache() {
+        let calls = web::Data::new(AtomicUsize::new(0));
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(cache()))
+                .app_data(calls.clone())
+                .wrap(from_fn(cache_responses))
+                .route("/seasons", web::get().to(seasons)),
+        )
+        .await;
+
+        let first = test::call_service(&app, test::TestRequest::get().uri("/seasons").to_request()).await;
+        assert_eq!(x_cache(&first), "MISS");
+        let etag = first.headers().get(header::ETAG).unwrap().clone();
+        let first_body = test::read_body(first).await;
+
+        let second = test::call_service(&app, test::TestRequest::get().uri("/seasons").to_request()).await;
+        assert_eq!(x_cache(&second), "HIT");
+        assert_eq!(second.headers().get(header::ETAG).unwrap(), &etag);
+        assert_eq!(test::read_body(second).await, first_body);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[actix_web::test]
+    async fn revalidation_is_answered_with_not_modified() {
+        let calls = web::Data::new(AtomicUsize::new(0));
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(cache()))
+                .app_data(calls.clone())
+                .wrap(from_fn(cache_responses))
+                .route("/seasons", web::get().to(seasons)),
+        )
+        .await;
+
+        let first = test::call_service(&app, test::TestRequest::get().uri("/seasons").to_request()).await;
+        let etag = first.headers().get(header::ETAG).unwrap().clone();
+        let last_modified = first.headers().get(header::LAST_MODIFIED).unwrap().clone();
+
+        let request = test::TestRequest::get()
+            .uri("/seasons")
+            .insert_header((header::IF_NONE_MATCH, etag.clone()))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers().get(header::ETAG).unwrap(), &etag);
+        assert!(test::read_body(response).await.is_empty());
+
+        let request = test::TestRequest::get()
+            .uri("/seasons")
+            .insert_header((header::IF_MODIFIED_SINCE, last_modified))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+        //A tag that doesn't match gets the full response, even with a matching date
+        let request = test::TestRequest::get()
+            .uri("/seasons")
+            .insert_header((header::IF_NONE_MATCH, "W/\"outdated\""))
+            .insert_header((header::IF_MODIFIED_SINCE, HttpDate::from(SystemTime::now())))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[actix_web::test]
+    async fn responses_computed_before_an_invalidation_are_not_stored() {
+        let calls = web::Data::new(AtomicUsize::new(0));
+        let cache = cache();
+        //The data changes while the response is being computed, so what it returns may already be outdated
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(cache.clone()))
+                .app_data(calls.clone())
+                .wrap(from_fn(cache_responses))
+                .route(
+                    "/seasons",
+                    web::get().to(|calls: web::Data<AtomicUsize>, cache: web::Data<ResponseCache>| async move {
+                        if calls.load(Ordering::SeqCst) == 0 {
+                            cache.invalidate();
+                        }
+                        seasons(calls).await
+                    }),
+                ),
+        )
+        .await;
+
+        let first = test::call_service(&app, test::TestRequest::get().uri("/seasons").to_request()).await;
+        assert_eq!(x_cache(&first), "MISS");
+        let second = test::call_service(&app, test::TestRequest::get().uri("/seasons").to_request()).await;
+        assert_eq!(x_cache(&second), "MISS");
+        let third = test::call_service(&app, test::TestRequest::get().uri("/seasons").to_request()).await;
+        assert_eq!(x_cache(&third), "HIT");
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        //An invalidation afterwards drops what was stored
+        cache.invalidate();
+        let fourth = test::call_service(&app, test::TestRequest::get().uri("/seasons").to_request()).await;
+        assert_eq!(x_cache(&fourth), "MISS");
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+}
@@ -1,79 +1,194 @@
-use std::{collections::HashMap, error::Error};
+use std::{
+    collections::{BTreeSet, HashMap},
+    error::Error,
+    time::Instant,
+};
 
-use sqlx::{Database, Executor, Pool, Postgres};
+use chrono::Utc;
+use sqlx::{Database, Executor, Pool, Postgres, Transaction};
 use tracing::warn;
 
-use crate::models::db_objects::{SeasonResult, Team};
+use crate::models::db_objects::{
+    BotPolicy, CurrentSeat, DriverInfo, DriverRating, DriverSeat, PointsEntry, Position,
+    ProgressionResult, RaceInfo, RaceResult, RatingChange, RatingHistoryEntry, RatingInput,
+    RecalculationRun, ResultImport, Seat, SeasonResult, Team, TeammateResult,
+};
+use crate::utils::metrics::{self, Timed};
 
-pub async fn update_season_results(pool: &Pool<Postgres>) -> Result<(), Box<dyn Error>> {
-    let mut tx = pool.begin().await?;
+#[derive(Debug)]
+pub enum RecalcError {
+    Database(sqlx::Error),
+    DriverWithoutTeam { driver_id: i32 },
+    TeamWithoutPoints { driver_id: i32, team_id: i32 },
+}
 
-    let seasons =
-        sqlx::query!("SELECT season FROM seasons WHERE finished=true and requires_recalc = true")
-            .fetch_all(&mut *tx)
-            .await?;
-    for season in seasons {
-        let season = season.season;
-        sqlx::query!("DELETE FROM season_result WHERE season = $1", season)
-            .execute(&mut *tx)
-            .await?;
-
-        let mut personal_results = sqlx::query!("
-            SELECT driver_id, sum(points) as total_points, max(team_id) as team_id
-            FROM result
-                JOIN public.has_result hr on result.result_id = hr.result_id
-                JOIN public.drives_for df on hr.seat_id = df.seat_id
-                JOIN public.drives_in di on hr.seat_id = di.seat_id
-                JOIN points p on result.season = p.season and result.position = p.position and result.pole = p.pole and result.leading_lap = p.leading_lap and result.fastest_lap = p.fastest_lap
-            WHERE p.season = $1
-            GROUP BY driver_id;", season).fetch_all(&mut *tx).await?;
-        personal_results.sort_unstable_by(|a, b| b.total_points.cmp(&a.total_points));
-        let personal_results: Vec<(usize, i32, i32)> = personal_results
-            .iter()
-            .enumerate()
-            .map(|(position, record)| {
-                (
-                    position + 1,
-                    record.driver_id,
-                    record
-                        .team_id
-                        .expect("Driver got Points despite not being in a team???"),
-                )
-            })
-            .collect();
-
-        let mut team_results = sqlx::query!("
-            SELECT team_id, sum(points) as total_points
-            FROM result
-                JOIN public.has_result hr on result.result_id = hr.result_id
-                JOIN public.drives_for df on hr.seat_id = df.seat_id
-                JOIN points p on result.season = p.season and result.position = p.position and result.pole = p.pole and result.leading_lap = p.leading_lap and result.fastest_lap = p.fastest_lap
-            WHERE p.season = $1
-            GROUP BY team_id;", season).fetch_all(&mut *tx).await?;
-        team_results.sort_unstable_by(|a, b| b.total_points.cmp(&a.total_points));
-
-        let mut team_result_map = HashMap::new();
-        team_results.iter().enumerate().for_each(|(index, item)| {
-            let _ = team_result_map.insert(item.team_id, index + 1);
-        });
+impl std::fmt::Display for RecalcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RecalcError::Database(e) => write!(f, "database error: {e}"),
+            RecalcError::DriverWithoutTeam { driver_id } => {
+                write!(f, "driver {driver_id} scored points without being in a team")
+            }
+            RecalcError::TeamWithoutPoints { driver_id, team_id } => write!(
+                f,
+                "driver {driver_id} scored points for team {team_id}, but the team has no points"
+            ),
+        }
+    }
+}
 
-        for (driver_id, position, team_position) in personal_results.iter().map(|(position, driver_id, team_id)| (driver_id, position, team_result_map.get(team_id).expect("Drvied scored points without being in ateam that scored points that season"))){
-            sqlx::query!("INSERT INTO season_result (driver_id, driver_result, team_result, season) VALUES ($1, $2, $3, $4)",
-            *driver_id,
-            *position as i32,
-            *team_position as i32,
-            season
-        ).execute(&mut *tx).await?;
+impl Error for RecalcError {}
+
+impl From<sqlx::Error> for RecalcError {
+    fn from(value: sqlx::Error) -> Self {
+        RecalcError::Database(value)
     }
+}
+
+//Recalculates every finished season that is flagged, limited to the given seasons when there are any.
+//Each season is rebuilt in its own transaction and every attempt is recorded in the recalculation history
+pub async fn update_season_results(
+    pool: &Pool<Postgres>,
+    only: Option<&BTreeSet<i32>>,
+) -> Result<Vec<RecalculationRun>, sqlx::Error> {
+    let only: Option<Vec<i32>> = only.map(|seasons| seasons.iter().copied().collect());
+    let seasons = sqlx::query_scalar!(
+        "SELECT season FROM seasons
+        WHERE finished = true AND requires_recalc = true AND ($1::INT[] IS NULL OR season = ANY($1))
+        ORDER BY season",
+        only.as_deref()
+    )
+    .fetch_all(pool)
+    .timed("get_seasons_to_recalculate")
+    .await?;
+
+    let mut runs = Vec::with_capacity(seasons.len());
+    for season in seasons {
+        let started_at = Utc::now();
+        let started = Instant::now();
+        let outcome = recalculate_season(pool, season).await;
+        let duration_ms = i32::try_from(started.elapsed().as_millis()).unwrap_or(i32::MAX);
+
+        let (rows_written, outcome, error) = match outcome {
+            Ok(rows) => (rows as i32, "succeeded", None),
+            Err(e) => (0, "failed", Some(e.to_string())),
+        };
+        metrics::METRICS.record_recalculation(outcome, started.elapsed().as_secs_f64());
+        let run = sqlx::query_as!(
+            RecalculationRun,
+            "INSERT INTO recalculation_history (season, started_at, duration_ms, rows_written, outcome, error)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING recalculation_id, season, started_at, duration_ms, rows_written, outcome, error",
+            season,
+            started_at,
+            duration_ms,
+            rows_written,
+            outcome,
+            error
+        )
+        .fetch_one(pool)
+        .timed("insert_recalculation_history")
+        .await?;
+        runs.push(run);
     }
 
-    sqlx::query!("UPDATE seasons SET requires_recalc=false")
+    Ok(runs)
+}
+
+//Rebuilds the standings of one season and clears its flag, returns the number of rows written
+pub async fn recalculate_season(pool: &Pool<Postgres>, season: i32) -> Result<usize, RecalcError> {
+    let mut tx = pool.begin().await?;
+
+    //Cleared first, so the row lock makes changes committed during the rebuild flag the season again
+    sqlx::query!(
+        "UPDATE seasons SET requires_recalc = false WHERE season = $1",
+        season
+    )
+    .execute(&mut *tx)
+    .timed("clear_requires_recalc")
+    .await?;
+
+    sqlx::query!("DELETE FROM season_result WHERE season = $1", season)
         .execute(&mut *tx)
+        .timed("delete_season_results")
         .await?;
 
+    //A result from a seat without a team leaves team_id NULL, so the season fails instead of losing those points
+    let mut personal_results = sqlx::query!("
+        SELECT driver_id, sum(points) as total_points,
+            CASE WHEN count(df.team_id) = count(*) THEN max(df.team_id) END as team_id
+        FROM result
+            JOIN public.has_result hr on result.result_id = hr.result_id
+            LEFT JOIN public.drives_for df on hr.seat_id = df.seat_id
+            JOIN public.drives_in di on hr.seat_id = di.seat_id
+            LEFT JOIN qualifying_result q on q.race_id = result.race_id and q.seat_id = hr.seat_id
+            JOIN points p on result.season = p.season and result.position = p.position and COALESCE(q.position = 1, result.pole) = p.pole and result.leading_lap = p.leading_lap and result.fastest_lap = p.fastest_lap
+        WHERE p.season = $1
+        GROUP BY driver_id;", season).fetch_all(&mut *tx).timed("get_personal_results").await?;
+    personal_results.sort_unstable_by_key(|record| std::cmp::Reverse(record.total_points));
+
+    let mut team_results = sqlx::query!("
+        SELECT team_id, sum(points) as total_points
+        FROM result
+            JOIN public.has_result hr on result.result_id = hr.result_id
+            JOIN public.drives_for df on hr.seat_id = df.seat_id
+            LEFT JOIN qualifying_result q on q.race_id = result.race_id and q.seat_id = hr.seat_id
+            JOIN points p on result.season = p.season and result.position = p.position and COALESCE(q.position = 1, result.pole) = p.pole and result.leading_lap = p.leading_lap and result.fastest_lap = p.fastest_lap
+        WHERE p.season = $1
+        GROUP BY team_id;", season).fetch_all(&mut *tx).timed("get_team_results").await?;
+    team_results.sort_unstable_by_key(|record| std::cmp::Reverse(record.total_points));
+
+    let team_result_map: HashMap<i32, usize> = team_results
+        .iter()
+        .enumerate()
+        .map(|(index, item)| (item.team_id, index + 1))
+        .collect();
+
+    for (index, record) in personal_results.iter().enumerate() {
+        let team_id = record.team_id.ok_or(RecalcError::DriverWithoutTeam {
+            driver_id: record.driver_id,
+        })?;
+        let team_position = team_result_map.get(&team_id).ok_or(RecalcError::TeamWithoutPoints {
+            driver_id: record.driver_id,
+            team_id,
+        })?;
+        sqlx::query!(
+            "INSERT INTO season_result (driver_id, driver_result, team_result, season) VALUES ($1, $2, $3, $4)",
+            record.driver_id,
+            (index + 1) as i32,
+            *team_position as i32,
+            season
+        )
+        .execute(&mut *tx)
+        .timed("insert_season_result")
+        .await?;
+    }
+
     tx.commit().await?;
 
-    Ok(())
+    Ok(personal_results.len())
+}
+
+pub async fn get_recalculation_history<'e, 'c, T>(
+    pool: T,
+    season: Option<i32>,
+    limit: i64,
+) -> Result<Vec<RecalculationRun>, sqlx::Error>
+where
+    T: 'e + Executor<'c, Database = Postgres>,
+{
+    sqlx::query_as!(
+        RecalculationRun,
+        "SELECT recalculation_id, season, started_at, duration_ms, rows_written, outcome, error
+        FROM recalculation_history
+        WHERE $1::INT IS NULL OR season = $1
+        ORDER BY started_at DESC, recalculation_id DESC
+        LIMIT $2",
+        season,
+        limit
+    )
+    .fetch_all(pool)
+    .await
 }
 
 pub async fn get_teams<'e, 'c, T>(pool: T, driver_id: i32) -> Result<Vec<Team>, sqlx::Error>
@@ -101,6 +216,607 @@ where
     T: 'e + Executor<'c, Database = Postgres>,
 {
     sqlx::query_as!(SeasonResult, 
-        "SELECT driver_result, team_result, season FROM season_result WHERE driver_id = $1"
+        "SELECT driver_result, team_result, season FROM season_result WHERE driver_id = $1 ORDER BY season"
         , driver_id).fetch_all(pool).await
 }
+
+pub async fn get_driver_info<'e, 'c, T>(pool: T, driver_id: i32) -> Result<DriverInfo, sqlx::Error>
+where
+    T: 'e + Executor<'c, Database = Postgres>,
+{
+    sqlx::query_as!(
+        DriverInfo,
+        "SELECT driver_id, username, driver_number, driver_image_url, birthday, country FROM driver WHERE driver_id = $1",
+        driver_id
+    )
+    .fetch_one(pool)
+    .await
+}
+
+//Every seat of a driver with its team and results in two queries, whatever the number of seats.
+//Seats are ordered by their first race, seats without results last
+pub async fn get_driver_seats(pool: &Pool<Postgres>, driver_id: i32) -> Result<Vec<Seat>, sqlx::Error> {
+    let seats = sqlx::query_as!(
+        DriverSeat,
+        "SELECT di.driver_id, di.seat_id, t.team_id, t.name, t.color
+        FROM drives_in di
+            JOIN drives_for df ON df.seat_id = di.seat_id
+            JOIN team t ON t.team_id = df.team_id
+        WHERE di.driver_id = $1
+        ORDER BY di.seat_id",
+        driver_id
+    )
+    .fetch_all(pool);
+
+    let results = sqlx::query!(
+        r#"
+        SELECT
+            has_result.seat_id,
+            result.position AS "position: Position",
+            bot_result,
+            points.pole AS pole,
+            points.leading_lap AS leading_lap,
+            points.fastest_lap as fastest_lap,
+            COALESCE(q.position, result.qualy_result) AS qualy_result,
+            result.season as season,
+            races.race_id as race_id,
+            race_name,
+            races.round as round,
+            points,
+            race_time,
+            gap_to_winner,
+            laps_completed,
+            best_lap_time,
+            pit_stops
+        FROM result
+        JOIN has_result ON result.result_id = has_result.result_id
+        JOIN drives_in ON has_result.seat_id = drives_in.seat_id
+        JOIN races ON result.race_id = races.race_id
+        LEFT JOIN qualifying_result q ON q.race_id = result.race_id AND q.seat_id = has_result.seat_id
+        JOIN points ON result.season = points.season
+            AND result.position = points.position
+            AND COALESCE(q.position = 1, result.pole) = points.pole
+            AND result.leading_lap = points.leading_lap
+            AND result.fastest_lap = points.fastest_lap
+            AND races.season = points.season
+        WHERE drives_in.driver_id = $1
+        ORDER BY result.season, races.round, races.race_id;
+        "#,
+        driver_id
+    )
+    .fetch_all(pool);
+
+    let (seats, results) = futures::try_join!(seats, results)?;
+
+    let mut seat_results: HashMap<i32, Vec<RaceResult>> = HashMap::new();
+    for row in results {
+        seat_results.entry(row.seat_id).or_default().push(RaceResult {
+            position: row.position,
+            bot_result: row.bot_result,
+            pole: row.pole,
+            leading_lap: row.leading_lap,
+            fastest_lap: row.fastest_lap,
+            qualy_result: row.qualy_result,
+            season: row.season,
+            race_id: row.race_id,
+            race_name: row.race_name,
+            round: row.round,
+            points: row.points,
+            race_time: row.race_time,
+            gap_to_winner: row.gap_to_winner,
+            laps_completed: row.laps_completed,
+            best_lap_time: row.best_lap_time,
+            pit_stops: row.pit_stops,
+        });
+    }
+
+    let mut seats: Vec<Seat> = seats
+        .into_iter()
+        .map(|seat| Seat {
+            results: seat_results.remove(&seat.seat_id).unwrap_or_default(),
+            seat_id: seat.seat_id,
+            team: Team {
+                team_id: seat.team_id,
+                name: seat.name,
+                color: seat.color,
+            },
+        })
+        .collect();
+    seats.sort_by_key(|seat| {
+        let first_race = seat.results.first().map(|result| (result.season, result.round));
+        (first_race.is_none(), first_race, seat.seat_id)
+    });
+
+    Ok(seats)
+}
+
+//Inserts the results of a race, or updates them when the seat already has a result for that race
+pub async fn import_race_results(
+    tx: &mut Transaction<'_, Postgres>,
+    race: &RaceInfo,
+    results: &[ResultImport],
+) -> Result<usize, sqlx::Error> {
+    for result in results {
+        let existing = sqlx::query_scalar!(
+            "SELECT result.result_id FROM result
+                JOIN has_result ON result.result_id = has_result.result_id
+            WHERE result.race_id = $1 AND has_result.seat_id = $2",
+            race.race_id,
+            result.seat_id
+        )
+        .fetch_optional(&mut **tx)
+        .await?;
+
+        match existing {
+            Some(result_id) => {
+                sqlx::query!(
+                    "UPDATE result SET position = $2, bot_result = $3, pole = $4, leading_lap = $5, fastest_lap = $6, qualy_result = $7,
+                        race_time = $8, gap_to_winner = $9, laps_completed = $10, best_lap_time = $11, pit_stops = $12
+                    WHERE result_id = $1",
+                    result_id,
+                    result.position as Position,
+                    result.bot_result,
+                    result.pole,
+                    result.leading_lap,
+                    result.fastest_lap,
+                    result.qualy_result,
+                    result.race_time,
+                    result.gap_to_winner,
+                    result.laps_completed,
+                    result.best_lap_time,
+                    result.pit_stops
+                )
+                .execute(&mut **tx)
+                .await?;
+            }
+            None => {
+                let result_id = sqlx::query_scalar!(
+                    "INSERT INTO result (position, bot_result, pole, leading_lap, fastest_lap, qualy_result, season, race_id,
+                        race_time, gap_to_winner, laps_completed, best_lap_time, pit_stops)
+                    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)
+                    RETURNING result_id",
+                    result.position as Position,
+                    result.bot_result,
+                    result.pole,
+                    result.leading_lap,
+                    result.fastest_lap,
+                    result.qualy_result,
+                    race.season,
+                    race.race_id,
+                    result.race_time,
+                    result.gap_to_winner,
+                    result.laps_completed,
+                    result.best_lap_time,
+                    result.pit_stops
+                )
+                .fetch_one(&mut **tx)
+                .await?;
+
+                sqlx::query!(
+                    "INSERT INTO has_result (result_id, seat_id) VALUES ($1, $2)",
+                    result_id,
+                    result.seat_id
+                )
+                .execute(&mut **tx)
+                .await?;
+            }
+        }
+    }
+
+    sqlx::query!(
+        "UPDATE seasons SET requires_recalc = true WHERE season = $1",
+        race.season
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(results.len())
+}
+
+pub async fn get_race<'e, 'c, T>(pool: T, race_id: i32) -> Result<RaceInfo, sqlx::Error>
+where
+    T: 'e + Executor<'c, Database = Postgres>,
+{
+    sqlx::query_as!(
+        RaceInfo,
+        "SELECT race_name, season, race_id, round FROM races WHERE race_id = $1",
+        race_id
+    )
+    .fetch_one(pool)
+    .await
+}
+
+//The seat each driver most recently raced in during a season. Without a result in the season yet, a seat that has
+//never been raced in is taken as the one for the season, and otherwise their newest seat is assumed
+pub async fn get_current_seats<'e, 'c, T>(
+    pool: T,
+    season: i32,
+    driver_ids: &[i32],
+) -> Result<Vec<CurrentSeat>, sqlx::Error>
+where
+    T: 'e + Executor<'c, Database = Postgres>,
+{
+    sqlx::query_as!(
+        CurrentSeat,
+        r#"SELECT DISTINCT ON (di.driver_id) di.driver_id, di.seat_id, t.team_id, t.name, t.color,
+            r.race_id IS NULL AND hr.result_id IS NOT NULL AS "assumed!"
+        FROM drives_in di
+            JOIN drives_for df ON df.seat_id = di.seat_id
+            JOIN team t ON t.team_id = df.team_id
+            LEFT JOIN has_result hr ON hr.seat_id = di.seat_id
+            LEFT JOIN result r ON r.result_id = hr.result_id AND r.season = $1
+        WHERE di.driver_id = ANY($2)
+        ORDER BY di.driver_id, r.race_id DESC NULLS LAST, hr.result_id IS NULL DESC, di.seat_id DESC"#,
+        season,
+        driver_ids
+    )
+    .fetch_all(pool)
+    .await
+}
+
+//Seats picked by hand, with the driver and team they belong to
+pub async fn get_seats<'e, 'c, T>(pool: T, seat_ids: &[i32]) -> Result<Vec<CurrentSeat>, sqlx::Error>
+where
+    T: 'e + Executor<'c, Database = Postgres>,
+{
+    sqlx::query_as!(
+        CurrentSeat,
+        r#"SELECT di.driver_id, di.seat_id, t.team_id, t.name, t.color, false AS "assumed!"
+        FROM drives_in di
+            JOIN drives_for df ON df.seat_id = di.seat_id
+            JOIN team t ON t.team_id = df.team_id
+        WHERE di.seat_id = ANY($1)"#,
+        seat_ids
+    )
+    .fetch_all(pool)
+    .await
+}
+
+//Every scored result of a season with the points it was worth, by the name the driver raced under
+pub async fn get_progression_results<'e, 'c, T>(
+    pool: T,
+    season: i32,
+) -> Result<Vec<ProgressionResult>, sqlx::Error>
+where
+    T: 'e + Executor<'c, Database = Postgres>,
+{
+    let rows = sqlx::query!(
+        r#"
+        SELECT
+            r.race_id,
+            r.race_name,
+            r.round,
+            d.driver_id,
+            COALESCE(da.username, d.username) AS "username!",
+            t.team_id,
+            t.name AS team_name,
+            t.color AS team_color,
+            result.position,
+            COALESCE(p.points, 0) AS "points!",
+            result.bot_result
+        FROM result
+        JOIN has_result hr ON result.result_id = hr.result_id
+        JOIN drives_in di ON hr.seat_id = di.seat_id
+        JOIN driver d ON di.driver_id = d.driver_id
+        JOIN drives_for df ON hr.seat_id = df.seat_id
+        JOIN team t ON df.team_id = t.team_id
+        JOIN races r ON result.race_id = r.race_id
+        LEFT JOIN qualifying_result q ON q.race_id = result.race_id AND q.seat_id = hr.seat_id
+        LEFT JOIN points p ON result.season = p.season
+            AND result.position = p.position
+            AND COALESCE(q.position = 1, result.pole) = p.pole
+            AND result.leading_lap = p.leading_lap
+            AND result.fastest_lap = p.fastest_lap
+        LEFT JOIN LATERAL (
+            SELECT username FROM driver_alias
+            WHERE driver_alias.driver_id = d.driver_id
+                AND result.season BETWEEN first_season AND COALESCE(last_season, result.season)
+            ORDER BY first_season DESC LIMIT 1
+        ) da ON true
+        WHERE result.season = $1
+        ORDER BY r.round, r.race_id;
+        "#,
+        season
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| ProgressionResult {
+            race_id: row.race_id,
+            race_name: row.race_name,
+            round: row.round,
+            driver_id: row.driver_id,
+            username: row.username,
+            team: Team {
+                team_id: row.team_id,
+                name: row.team_name,
+                color: row.team_color,
+            },
+            position: Position::new(row.position),
+            points: row.points,
+            bot_result: row.bot_result,
+        })
+        .collect())
+}
+
+//Races of a season that have no results yet, in calendar order
+pub async fn get_remaining_races<'e, 'c, T>(pool: T, season: i32) -> Result<Vec<RaceInfo>, sqlx::Error>
+where
+    T: 'e + Executor<'c, Database = Postgres>,
+{
+    sqlx::query_as!(
+        RaceInfo,
+        "SELECT race_name, season, race_id, round FROM races
+        WHERE season = $1 AND NOT EXISTS (SELECT 1 FROM result WHERE result.race_id = races.race_id)
+        ORDER BY round",
+        season
+    )
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn get_points_scheme<'e, 'c, T>(pool: T, season: i32) -> Result<Vec<PointsEntry>, sqlx::Error>
+where
+    T: 'e + Executor<'c, Database = Postgres>,
+{
+    sqlx::query_as!(
+        PointsEntry,
+        "SELECT position, pole, leading_lap, fastest_lap, points FROM points WHERE season = $1",
+        season
+    )
+    .fetch_all(pool)
+    .await
+}
+
+//Every classified result of every season, in the order the races were run
+pub async fn get_rating_inputs<'e, 'c, T>(pool: T) -> Result<Vec<RatingInput>, sqlx::Error>
+where
+    T: 'e + Executor<'c, Database = Postgres>,
+{
+    sqlx::query_as!(
+        RatingInput,
+        r#"SELECT result.race_id, di.driver_id, result.position AS "position: Position", result.bot_result
+        FROM result
+            JOIN has_result hr ON result.result_id = hr.result_id
+            JOIN drives_in di ON hr.seat_id = di.seat_id
+            JOIN races r ON result.race_id = r.race_id
+        ORDER BY r.season, r.round, r.race_id, result.position"#
+    )
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn replace_rating_history(
+    tx: &mut Transaction<'_, Postgres>,
+    changes: &[RatingChange],
+    changes_without_bots: &[RatingChange],
+) -> Result<(), sqlx::Error> {
+    sqlx::query!("DELETE FROM rating_history")
+        .execute(&mut **tx)
+        .await?;
+
+    let all = changes.iter().chain(changes_without_bots);
+    let driver_ids: Vec<i32> = all.clone().map(|change| change.driver_id).collect();
+    let race_ids: Vec<i32> = all.clone().map(|change| change.race_id).collect();
+    let before: Vec<f64> = all.clone().map(|change| change.rating_before).collect();
+    let after: Vec<f64> = all.clone().map(|change| change.rating_after).collect();
+    let bot_results: Vec<bool> = all.map(|change| change.bot_result).collect();
+    let bots_excluded: Vec<bool> = std::iter::repeat_n(false, changes.len())
+        .chain(std::iter::repeat_n(true, changes_without_bots.len()))
+        .collect();
+    sqlx::query!(
+        "INSERT INTO rating_history (driver_id, race_id, rating_before, rating_after, bot_result, bots_excluded)
+        SELECT * FROM UNNEST($1::INT[], $2::INT[], $3::FLOAT8[], $4::FLOAT8[], $5::BOOL[], $6::BOOL[])",
+        &driver_ids,
+        &race_ids,
+        &before,
+        &after,
+        &bot_results,
+        &bots_excluded
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+//With bots=exclude the history comes from the replay that never saw a bot result
+pub async fn get_rating_history<'e, 'c, T>(
+    pool: T,
+    driver_id: i32,
+    bots: BotPolicy,
+) -> Result<Vec<RatingHistoryEntry>, sqlx::Error>
+where
+    T: 'e + Executor<'c, Database = Postgres>,
+{
+    sqlx::query_as!(
+        RatingHistoryEntry,
+        "SELECT r.race_id, r.race_name, r.season, r.round, rh.rating_before, rh.rating_after, rh.bot_result
+        FROM rating_history rh
+            JOIN races r ON r.race_id = rh.race_id
+        WHERE rh.driver_id = $1 AND rh.bots_excluded = $2
+        ORDER BY r.season, r.round, r.race_id",
+        driver_id,
+        bots == BotPolicy::Exclude
+    )
+    .fetch_all(pool)
+    .await
+}
+
+//Latest rating of every rated driver, best first
+pub async fn get_driver_ratings<'e, 'c, T>(
+    pool: T,
+    bots: BotPolicy,
+) -> Result<Vec<DriverRating>, sqlx::Error>
+where
+    T: 'e + Executor<'c, Database = Postgres>,
+{
+    let rows = sqlx::query!(
+        r#"
+        WITH latest AS (
+            SELECT DISTINCT ON (rh.driver_id) rh.driver_id, rh.rating_after
+            FROM rating_history rh
+                JOIN races r ON r.race_id = rh.race_id
+            WHERE rh.bots_excluded = $1
+            ORDER BY rh.driver_id, r.season DESC, r.round DESC, r.race_id DESC
+        ), counts AS (
+            SELECT driver_id, count(*) AS races, count(*) FILTER (WHERE bot_result) AS bot_races
+            FROM rating_history
+            WHERE bots_excluded = $1
+            GROUP BY driver_id
+        )
+        SELECT d.driver_id, d.username, d.driver_number, d.driver_image_url, d.country, d.birthday,
+            latest.rating_after AS rating, counts.races AS "races!", counts.bot_races AS "bot_races!"
+        FROM latest
+            JOIN counts ON counts.driver_id = latest.driver_id
+            JOIN driver d ON d.driver_id = latest.driver_id
+        ORDER BY latest.rating_after DESC, d.driver_id
+        "#,
+        bots == BotPolicy::Exclude
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .enumerate()
+        .map(|(index, row)| DriverRating {
+            rank: index as i32 + 1,
+            driver_info: DriverInfo {
+                driver_id: row.driver_id,
+                username: row.username,
+                driver_number: row.driver_number,
+                driver_image_url: row.driver_image_url,
+                country: row.country,
+                birthday: row.birthday,
+            },
+            rating: row.rating,
+            races: row.races,
+            bot_races: bots.flags().then_some(row.bot_races),
+        })
+        .collect())
+}
+
+//Results per team and season, limited to a season, a team and/or the team seasons a driver took part in
+pub async fn get_teammate_results<'e, 'c, T>(
+    pool: T,
+    season: Option<i32>,
+    team_id: Option<i32>,
+    driver_id: Option<i32>,
+) -> Result<Vec<TeammateResult>, sqlx::Error>
+where
+    T: 'e + Executor<'c, Database = Postgres>,
+{
+    let rows = sqlx::query!(
+        r#"
+        SELECT
+            result.season,
+            df.team_id,
+            result.race_id,
+            d.driver_id,
+            d.username,
+            result.position,
+            COALESCE(q.position, result.qualy_result) AS qualifying,
+            COALESCE(p.points, 0) AS "points!",
+            result.bot_result
+        FROM result
+            JOIN has_result hr ON result.result_id = hr.result_id
+            JOIN drives_in di ON hr.seat_id = di.seat_id
+            JOIN driver d ON di.driver_id = d.driver_id
+            JOIN drives_for df ON hr.seat_id = df.seat_id
+            LEFT JOIN qualifying_result q ON q.race_id = result.race_id AND q.seat_id = hr.seat_id
+            LEFT JOIN points p ON result.season = p.season
+                AND result.position = p.position
+                AND COALESCE(q.position = 1, result.pole) = p.pole
+                AND result.leading_lap = p.leading_lap
+                AND result.fastest_lap = p.fastest_lap
+        WHERE ($1::INT IS NULL OR result.season = $1)
+            AND ($2::INT IS NULL OR df.team_id = $2)
+            AND ($3::INT IS NULL OR (result.season, df.team_id) IN (
+                SELECT r.season, f.team_id
+                FROM result r
+                    JOIN has_result h ON r.result_id = h.result_id
+                    JOIN drives_in i ON h.seat_id = i.seat_id
+                    JOIN drives_for f ON h.seat_id = f.seat_id
+                WHERE i.driver_id = $3
+            ))
+        ORDER BY result.season, df.team_id, result.race_id
+        "#,
+        season,
+        team_id,
+        driver_id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| TeammateResult {
+            season: row.season,
+            team_id: row.team_id,
+            race_id: row.race_id,
+            driver_id: row.driver_id,
+            username: row.username,
+            position: Position::new(row.position),
+            qualifying: row.qualifying,
+            points: row.points,
+            bot_result: row.bot_result,
+        })
+        .collect())
+}
+
+//Versions of the migrations that ran successfully, oldest first
+pub async fn get_applied_migrations<'e, 'c, T>(pool: T) -> Result<Vec<i64>, sqlx::Error>
+where
+    T: 'e + Executor<'c, Database = Postgres>,
+{
+    sqlx::query_scalar!("SELECT version FROM _sqlx_migrations WHERE success = true ORDER BY version")
+        .fetch_all(pool)
+        .await
+}
+
+//Refills a shared token bucket for the time since it was last used and takes a token from it if one is left.
+//Returns the tokens left and whether one was taken, a single statement so concurrent servers can't both take the last one
+pub async fn take_rate_limit_token<'e, 'c, T>(
+    pool: T,
+    key: &str,
+    capacity: f64,
+    per_second: f64,
+) -> Result<(f64, bool), sqlx::Error>
+where
+    T: 'e + Executor<'c, Database = Postgres>,
+{
+    let bucket = sqlx::query!(
+        r#"INSERT INTO rate_limit_bucket AS bucket (key, tokens, allowed, updated_at)
+        VALUES ($1, $2::FLOAT8 - 1, true, now())
+        ON CONFLICT (key) DO UPDATE SET
+            tokens = LEAST($2, bucket.tokens + EXTRACT(EPOCH FROM now() - bucket.updated_at)::FLOAT8 * $3)
+                - CASE WHEN LEAST($2, bucket.tokens + EXTRACT(EPOCH FROM now() - bucket.updated_at)::FLOAT8 * $3) >= 1
+                    THEN 1 ELSE 0 END,
+            allowed = LEAST($2, bucket.tokens + EXTRACT(EPOCH FROM now() - bucket.updated_at)::FLOAT8 * $3) >= 1,
+            updated_at = now()
+        RETURNING tokens, allowed"#,
+        key,
+        capacity,
+        per_second
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok((bucket.tokens, bucket.allowed))
+}
+
+//Buckets untouched for this long have refilled completely, so forgetting them changes nothing
+pub async fn delete_idle_rate_limit_buckets<'e, 'c, T>(pool: T, idle_secs: f64) -> Result<u64, sqlx::Error>
+where
+    T: 'e + Executor<'c, Database = Postgres>,
+{
+    let deleted = sqlx::query!(
+        "DELETE FROM rate_limit_bucket WHERE updated_at < now() - make_interval(secs => $1)",
+        idle_secs
+    )
+    .execute(pool)
+    .await?;
+    Ok(deleted.rows_affected())
+}
@@ -0,0 +1,180 @@
+use std::future::Future;
+use std::sync::LazyLock;
+use std::time::Instant;
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::Error;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry, TextEncoder,
+};
+use sqlx::{Pool, Postgres};
+use tracing::{info_span, Instrument};
+
+//Process wide, so repository functions can record their timings without the registry being passed around
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+pub struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_request_duration: HistogramVec,
+    db_query_duration: HistogramVec,
+    db_query_errors: IntCounterVec,
+    db_pool_connections: IntGauge,
+    db_pool_idle_connections: IntGauge,
+    db_pool_max_connections: IntGauge,
+    recalculation_runs: IntCounterVec,
+    recalculation_duration: HistogramVec,
+    cache_requests: IntCounterVec,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new();
+
+        let http_requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests handled, by route and status"),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::new("http_request_duration_seconds", "Time spent handling HTTP requests"),
+            &["method", "route"],
+        )
+        .unwrap();
+        let db_query_duration = HistogramVec::new(
+            HistogramOpts::new("db_query_duration_seconds", "Time spent in repository calls")
+                .buckets(vec![0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5]),
+            &["query"],
+        )
+        .unwrap();
+        let db_query_errors = IntCounterVec::new(
+            Opts::new("db_query_errors_total", "Repository calls that returned an error"),
+            &["query"],
+        )
+        .unwrap();
+        let db_pool_connections =
+            IntGauge::new("db_pool_connections", "Open database connections").unwrap();
+        let db_pool_idle_connections =
+            IntGauge::new("db_pool_idle_connections", "Open database connections not in use").unwrap();
+        let db_pool_max_connections =
+            IntGauge::new("db_pool_max_connections", "Most database connections the pool will open").unwrap();
+        let recalculation_runs = IntCounterVec::new(
+            Opts::new("recalculation_runs_total", "Season recalculations, by outcome"),
+            &["outcome"],
+        )
+        .unwrap();
+        let recalculation_duration = HistogramVec::new(
+            HistogramOpts::new("recalculation_duration_seconds", "Time spent recalculating a season")
+                .buckets(vec![0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0]),
+            &["outcome"],
+        )
+        .unwrap();
+        let cache_requests = IntCounterVec::new(
+            Opts::new("response_cache_requests_total", "Cacheable requests, by whether they were served from the cache"),
+            &["result"],
+        )
+        .unwrap();
+
+        registry.register(Box::new(http_requests.clone())).unwrap();
+        registry.register(Box::new(http_request_duration.clone())).unwrap();
+        registry.register(Box::new(db_query_duration.clone())).unwrap();
+        registry.register(Box::new(db_query_errors.clone())).unwrap();
+        registry.register(Box::new(db_pool_connections.clone())).unwrap();
+        registry.register(Box::new(db_pool_idle_connections.clone())).unwrap();
+        registry.register(Box::new(db_pool_max_connections.clone())).unwrap();
+        registry.register(Box::new(recalculation_runs.clone())).unwrap();
+        registry.register(Box::new(recalculation_duration.clone())).unwrap();
+        registry.register(Box::new(cache_requests.clone())).unwrap();
+
+        Metrics {
+            registry,
+            http_requests,
+            http_request_duration,
+            db_query_duration,
+            db_query_errors,
+            db_pool_connections,
+            db_pool_idle_connections,
+            db_pool_max_connections,
+            recalculation_runs,
+            recalculation_duration,
+            cache_requests,
+        }
+    }
+
+    pub fn record_recalculation(&self, outcome: &str, seconds: f64) {
+        self.recalculation_runs.with_label_values(&[outcome]).inc();
+        self.recalculation_duration
+            .with_label_values(&[outcome])
+            .observe(seconds);
+    }
+
+    pub fn record_cache(&self, hit: bool) {
+        self.cache_requests
+            .with_label_values(&[if hit { "hit" } else { "miss" }])
+            .inc();
+    }
+
+    //Everything registered, in the Prometheus text format
+    pub fn render(&self, pool: &Pool<Postgres>) -> Result<String, prometheus::Error> {
+        self.db_pool_connections.set(i64::from(pool.size()));
+        self.db_pool_idle_connections.set(pool.num_idle() as i64);
+        self.db_pool_max_connections
+            .set(i64::from(pool.options().get_max_connections()));
+
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8_lossy(&buffer).into_owned())
+    }
+}
+
+//Times a database call, labelled with the name of the query, and runs it in a span of the same name
+pub async fn timed<T, E>(query: &'static str, future: impl Future<Output = Result<T, E>>) -> Result<T, E> {
+    let started = Instant::now();
+    let result = future.instrument(info_span!("db_query", db.query = query)).await;
+    METRICS
+        .db_query_duration
+        .with_label_values(&[query])
+        .observe(started.elapsed().as_secs_f64());
+    if result.is_err() {
+        METRICS.db_query_errors.with_label_values(&[query]).inc();
+    }
+    result
+}
+
+//Applied where a query is awaited, e.g. `db::get_race(pool, race_id).timed("get_race").await`
+pub trait Timed<T, E>: Future<Output = Result<T, E>> + Sized {
+    fn timed(self, query: &'static str) -> impl Future<Output = Result<T, E>> {
+        timed(query, self)
+    }
+}
+
+impl<T, E, F: Future<Output = Result<T, E>>> Timed<T, E> for F {}
+
+//Counts and times every request by its route pattern, so path parameters don't each get their own series
+pub async fn record_requests(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let method = req.method().to_string();
+    let started = Instant::now();
+
+    let response = next.call(req).await?;
+
+    //Only known once routing has happened
+    let route = response
+        .request()
+        .match_pattern()
+        .unwrap_or_else(|| "unmatched".to_string());
+    let status = response.status().as_u16().to_string();
+    METRICS
+        .http_requests
+        .with_label_values(&[&method, &route, &status])
+        .inc();
+    METRICS
+        .http_request_duration
+        .with_label_values(&[&method, &route])
+        .observe(started.elapsed().as_secs_f64());
+    Ok(response)
+}
@@ -1 +1,12 @@
-pub mod db;
\ No newline at end of file
+pub mod cache;
+pub mod db;
+pub mod metrics;
+pub mod rate_limit;
+pub mod rating;
+pub mod recalculation;
+pub mod request_id;
+pub mod scenarios;
+pub mod standings;
+pub mod teammates;
+#[cfg(test)]
+pub mod test_db;
@@ -0,0 +1,358 @@
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use actix_web::body::BoxBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::middleware::Next;
+use actix_web::{web, Error, Responder};
+use sqlx::{Pool, Postgres};
+use tokio::task::JoinHandle;
+use tokio::time::{interval, Instant};
+use tracing::{debug, warn};
+
+use crate::config::{RateLimitBackend, RateLimitConfig, RouteLimit};
+use crate::models::api_response::ApiResponse;
+use crate::utils::db;
+use crate::utils::metrics::Timed;
+
+//How often buckets that have refilled completely are forgotten
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+//Every group has limits of its own, so browsing drivers doesn't use up the budget for seasons
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteGroup {
+    Driver,
+    Season,
+    Team,
+    Race,
+    Export,
+    Admin,
+}
+
+impl RouteGroup {
+    const ALL: [RouteGroup; 6] = [
+        RouteGroup::Driver,
+        RouteGroup::Season,
+        RouteGroup::Team,
+        RouteGroup::Race,
+        RouteGroup::Export,
+        RouteGroup::Admin,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            RouteGroup::Driver => "driver",
+            RouteGroup::Season => "season",
+            RouteGroup::Team => "team",
+            RouteGroup::Race => "race",
+            RouteGroup::Export => "export",
+            RouteGroup::Admin => "admin",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Decision {
+    Allowed,
+    //How long until the bucket holds a whole token again
+    Limited(Duration),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+fn capacity(limit: RouteLimit) -> f64 {
+    f64::from(limit.burst)
+}
+
+fn per_second(limit: RouteLimit) -> f64 {
+    f64::from(limit.per_minute) / 60.0
+}
+
+//Refills the bucket for the time since it was last used and takes a token if a whole one is left.
+//A new client starts with a full bucket, the same as the shared store does
+fn take(bucket: Option<Bucket>, now: Instant, limit: RouteLimit) -> (Bucket, bool) {
+    let tokens = match bucket {
+        Some(bucket) => {
+            let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+            capacity(limit).min(bucket.tokens + elapsed * per_second(limit))
+        }
+        None => capacity(limit),
+    };
+    let allowed = tokens >= 1.0;
+    let tokens = if allowed { tokens - 1.0 } else { tokens };
+    (Bucket { tokens, updated: now }, allowed)
+}
+
+fn decide(tokens: f64, allowed: bool, limit: RouteLimit) -> Decision {
+    if allowed {
+        Decision::Allowed
+    } else {
+        Decision::Limited(Duration::from_secs_f64((1.0 - tokens).max(0.0) / per_second(limit)))
+    }
+}
+
+#[derive(Debug)]
+enum Store {
+    Memory(Mutex<HashMap<String, Bucket>>),
+    Postgres(Pool<Postgres>),
+}
+
+//Token buckets per client and route group, kept in memory or shared between instances through the database
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    store: Arc<Store>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig, pool: Pool<Postgres>) -> Self {
+        let store = match config.backend {
+            RateLimitBackend::Memory => Store::Memory(Mutex::new(HashMap::new())),
+            RateLimitBackend::Postgres => Store::Postgres(pool),
+        };
+        RateLimiter {
+            config,
+            store: Arc::new(store),
+        }
+    }
+
+    fn limit(&self, group: RouteGroup) -> RouteLimit {
+        match group {
+            RouteGroup::Driver => self.config.driver,
+            RouteGroup::Season => self.config.season,
+            RouteGroup::Team => self.config.team,
+            RouteGroup::Race => self.config.race,
+            RouteGroup::Export => self.config.export,
+            RouteGroup::Admin => self.config.admin,
+        }
+    }
+
+    async fn take(&self, key: &str, limit: RouteLimit) -> Decision {
+        match self.store.as_ref() {
+            Store::Memory(buckets) => {
+                let mut buckets = buckets.lock().unwrap();
+                let (bucket, allowed) = take(buckets.get(key).copied(), Instant::now(), limit);
+                buckets.insert(key.to_string(), bucket);
+                decide(bucket.tokens, allowed, limit)
+            }
+            //Requests are let through when the database can't be asked, rather than failing every one of them
+            Store::Postgres(pool) => {
+                let taken = db::take_rate_limit_token(pool, key, capacity(limit), per_second(limit))
+                    .timed("take_rate_limit_token")
+                    .await;
+                match taken {
+                    Ok((tokens, allowed)) => decide(tokens, allowed, limit),
+                    Err(e) => {
+                        warn!("Failed to check the rate limit, allowing the request: {:?}", e);
+                        Decision::Allowed
+                    }
+                }
+            }
+        }
+    }
+
+    //Long enough for the slowest group to refill, a bucket idle for longer is full and the same as no bucket
+    fn idle_after(&self) -> Duration {
+        RouteGroup::ALL
+            .into_iter()
+            .map(|group| self.limit(group))
+            .filter(|limit| limit.per_minute > 0)
+            .map(|limit| Duration::from_secs_f64(capacity(limit) / per_second(limit)))
+            .max()
+            .unwrap_or_default()
+    }
+
+    async fn prune(&self) {
+        let idle_after = self.idle_after();
+        match self.store.as_ref() {
+            Store::Memory(buckets) => {
+                let now = Instant::now();
+                let mut buckets = buckets.lock().unwrap();
+                buckets.retain(|_, bucket| now.saturating_duration_since(bucket.updated) < idle_after);
+            }
+            Store::Postgres(pool) => {
+                let deleted = db::delete_idle_rate_limit_buckets(pool, idle_after.as_secs_f64())
+                    .timed("delete_idle_rate_limit_buckets")
+                    .await;
+                if let Err(e) = deleted {
+                    warn!("Failed to delete idle rate limit buckets: {:?}", e);
+                }
+            }
+        }
+    }
+
+    //Keeps the store from growing with every client that ever made a request
+    pub fn spawn_pruning(&self) -> JoinHandle<()> {
+        let limiter = self.clone();
+        tokio::spawn(async move {
+            let mut ticks = interval(PRUNE_INTERVAL);
+            ticks.tick().await;
+            loop {
+                ticks.tick().await;
+                limiter.prune().await;
+            }
+        })
+    }
+}
+
+//The for= addresses of Forwarded, or X-Forwarded-For when there is no Forwarded, the nearest proxy's entry last
+fn forwarded_for(req: &ServiceRequest) -> Vec<String> {
+    let forwarded: Vec<String> = req
+        .headers()
+        .get_all(header::FORWARDED)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|element| {
+            element.split(';').find_map(|pair| {
+                let (name, value) = pair.trim().split_once('=')?;
+                name.eq_ignore_ascii_case("for")
+                    .then(|| value.trim().trim_matches('"').to_string())
+            })
+        })
+        .collect();
+    if !forwarded.is_empty() {
+        return forwarded;
+    }
+
+    req.headers()
+        .get_all(header::X_FORWARDED_FOR)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|address| address.trim().to_string())
+        .filter(|address| !address.is_empty())
+        .collect()
+}
+
+//Proxies may add the port, which changes with every connection, so only the ip is kept
+fn without_port(address: &str) -> String {
+    if let Ok(address) = address.parse::<SocketAddr>() {
+        return address.ip().to_string();
+    }
+    address.trim_start_matches('[').trim_end_matches(']').to_string()
+}
+
+//Every trusted proxy adds the address it was connected from, so counting them off from the right lands on the
+//address the first trusted proxy saw. With fewer entries than proxies every entry came from one of them
+fn client_address(req: &ServiceRequest, trusted_proxies: usize) -> Option<String> {
+    if trusted_proxies > 0 {
+        let forwarded = forwarded_for(req);
+        if let Some(address) = forwarded.get(forwarded.len().saturating_sub(trusted_proxies)) {
+            return Some(without_port(address));
+        }
+    }
+    req.peer_addr().map(|address| address.ip().to_string())
+}
+
+//A bucket for the client address, and one for the token when the request carries one.
+//A token does not lift the address limit, otherwise sending a new token with every request would get around it
+fn client_keys(req: &ServiceRequest, group: RouteGroup, trusted_proxies: usize) -> Vec<String> {
+    let address = client_address(req, trusted_proxies);
+    let mut keys = vec![format!(
+        "{}:ip:{}",
+        group.name(),
+        address.as_deref().unwrap_or("unknown")
+    )];
+
+    //Hashed, so tokens don't end up in the shared store
+    if let Some(token) = req.headers().get(header::AUTHORIZATION) {
+        let mut hasher = DefaultHasher::new();
+        token.as_bytes().hash(&mut hasher);
+        keys.push(format!("{}:token:{:016x}", group.name(), hasher.finish()));
+    }
+    keys
+}
+
+//Answers with 429 Too Many Requests and a Retry-After once a client has used up its bucket for the group
+pub async fn rate_limit(
+    group: RouteGroup,
+    req: ServiceRequest,
+    next: Next<BoxBody>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let limiter = match req.app_data::<web::Data<RateLimiter>>() {
+        Some(limiter) if limiter.config.enabled => limiter.clone(),
+        _ => return next.call(req).await,
+    };
+    let limit = limiter.limit(group);
+    if limit.per_minute == 0 {
+        return next.call(req).await;
+    }
+
+    for key in client_keys(&req, group, limiter.config.trusted_proxies) {
+        if let Decision::Limited(wait) = limiter.take(&key, limit).await {
+            let retry_after = wait.as_secs_f64().ceil().max(1.0) as u64;
+            debug!(key, retry_after, "Rate limited");
+            let mut response = ApiResponse::new_too_many_requests::<()>("Too many requests, try again later")
+                .respond_to(req.request());
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(retry_after));
+            return Ok(req.into_response(response));
+        }
+    }
+    next.call(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bucket_allows_a_burst_then_refills_over_time() {
+        let limit = RouteLimit {
+            burst: 2,
+            per_minute: 60,
+        };
+        let start = Instant::now();
+
+        let (bucket, first) = take(None, start, limit);
+        let (bucket, second) = take(Some(bucket), start, limit);
+        let (bucket, third) = take(Some(bucket), start, limit);
+        assert!(first && second && !third);
+        assert_eq!(decide(bucket.tokens, third, limit), Decision::Limited(Duration::from_secs(1)));
+
+        let (bucket, half) = take(Some(bucket), start + Duration::from_millis(500), limit);
+        assert!(!half);
+        let (_, refilled) = take(Some(bucket), start + Duration::from_secs(1), limit);
+        assert!(refilled);
+
+        //Refilling stops at the burst size
+        let (bucket, _) = take(Some(bucket), start + Duration::from_secs(3600), limit);
+        assert_eq!(bucket.tokens, 1.0);
+    }
+
+    fn forwarded_request(headers: &[(&str, &str)]) -> ServiceRequest {
+        let mut request = actix_web::test::TestRequest::default().peer_addr("10.0.0.2:40000".parse().unwrap());
+        for &(name, value) in headers {
+            request = request.append_header((name, value));
+        }
+        request.to_srv_request()
+    }
+
+    #[test]
+    fn client_address_is_counted_from_the_right() {
+        //Behind two proxies: the client made up the first entry, the outer proxy added the client and the inner one the outer
+        let request = forwarded_request(&[("X-Forwarded-For", "1.1.1.1, 203.0.113.7"), ("X-Forwarded-For", "10.0.0.1")]);
+        assert_eq!(client_address(&request, 0).as_deref(), Some("10.0.0.2"));
+        assert_eq!(client_address(&request, 1).as_deref(), Some("10.0.0.1"));
+        assert_eq!(client_address(&request, 2).as_deref(), Some("203.0.113.7"));
+        assert_eq!(client_address(&request, 5).as_deref(), Some("1.1.1.1"));
+
+        //Forwarded wins over X-Forwarded-For, ports and quotes are dropped
+        let request = forwarded_request(&[
+            ("Forwarded", "for=1.1.1.1, for=\"[2001:db8::1]:4711\";proto=https"),
+            ("X-Forwarded-For", "198.51.100.1"),
+        ]);
+        assert_eq!(client_address(&request, 1).as_deref(), Some("2001:db8::1"));
+
+        let request = forwarded_request(&[]);
+        assert_eq!(client_address(&request, 1).as_deref(), Some("10.0.0.2"));
+    }
+}
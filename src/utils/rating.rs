@@ -0,0 +1,202 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use itertools::Itertools;
+use sqlx::{Pool, Postgres};
+
+use crate::config::RatingConfig;
+use crate::models::db_objects::{Position, RatingChange, RatingInput};
+use crate::utils::db;
+use crate::utils::metrics::Timed;
+
+fn expected_score(rating: f64, opponent: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf((opponent - rating) / 400.0))
+}
+
+//Multiplayer Elo: every race is scored as a duel against each other driver in the classification.
+//A DNF loses to every finisher and a DSQ to every DNF, two drivers out the same way draw. A DNS never raced,
+//so it is left out and neither rated nor counted against anyone.
+//Duels involving an AI driven result count for bot_weight, drivers without any duel that counts are not rated for that race
+pub fn rate(results: &[RatingInput], config: &RatingConfig) -> Vec<RatingChange> {
+    let mut ratings: HashMap<i32, f64> = HashMap::new();
+    let mut changes = Vec::with_capacity(results.len());
+
+    for (race_id, race) in &results.iter().chunk_by(|result| result.race_id) {
+        let race: Vec<&RatingInput> = race.filter(|result| result.position != Position::Dns).collect();
+        if race.len() < 2 {
+            continue;
+        }
+        let before: Vec<f64> = race
+            .iter()
+            .map(|result| *ratings.get(&result.driver_id).unwrap_or(&config.initial))
+            .collect();
+
+        for (index, result) in race.iter().enumerate() {
+            let mut delta = 0.0;
+            let mut weight = 0.0;
+            for (other_index, other) in race.iter().enumerate() {
+                if index == other_index {
+                    continue;
+                }
+                let pair_weight = if result.bot_result || other.bot_result {
+                    config.bot_weight
+                } else {
+                    1.0
+                };
+                let score = match result.position.cmp(&other.position) {
+                    Ordering::Less => 1.0,
+                    Ordering::Equal => 0.5,
+                    Ordering::Greater => 0.0,
+                };
+                delta += pair_weight * (score - expected_score(before[index], before[other_index]));
+                weight += pair_weight;
+            }
+            if weight == 0.0 {
+                continue;
+            }
+
+            let rating_after = before[index] + config.k_factor * delta / weight;
+            ratings.insert(result.driver_id, rating_after);
+            changes.push(RatingChange {
+                race_id,
+                driver_id: result.driver_id,
+                rating_before: before[index],
+                rating_after,
+                bot_result: result.bot_result,
+            });
+        }
+    }
+
+    changes
+}
+
+//Replays every race and replaces the stored rating history, returns the number of ratings written.
+//A second replay without the bot results backs bots=exclude, filtering the first one would leave their deltas in
+pub async fn rebuild(pool: &Pool<Postgres>, config: &RatingConfig) -> Result<usize, sqlx::Error> {
+    let results = db::get_rating_inputs(pool).timed("get_rating_inputs").await?;
+    let changes = rate(&results, config);
+    let without_bots: Vec<RatingInput> = results.into_iter().filter(|result| !result.bot_result).collect();
+    let changes_without_bots = rate(&without_bots, config);
+
+    let mut tx = pool.begin().await?;
+    db::replace_rating_history(&mut tx, &changes, &changes_without_bots)
+        .timed("replace_rating_history")
+        .await?;
+    tx.commit().await?;
+
+    Ok(changes.len() + changes_without_bots.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(bot_weight: f64) -> RatingConfig {
+        RatingConfig {
+            initial: 1500.0,
+            k_factor: 32.0,
+            bot_weight,
+        }
+    }
+
+    fn result(race_id: i32, driver_id: i32, position: Position, bot_result: bool) -> RatingInput {
+        RatingInput {
+            race_id,
+            driver_id,
+            position,
+            bot_result,
+        }
+    }
+
+    fn after(changes: &[RatingChange], race_id: i32, driver_id: i32) -> Option<f64> {
+        changes
+            .iter()
+            .find(|change| change.race_id == race_id && change.driver_id == driver_id)
+            .map(|change| change.rating_after)
+    }
+
+    #[test]
+    fn winners_gain_what_losers_lose() {
+        let results = vec![
+            result(1, 1, Position::Finished(1), false),
+            result(1, 2, Position::Finished(2), false),
+            result(1, 3, Position::Dnf, false),
+            result(2, 3, Position::Finished(1), false),
+            result(2, 1, Position::Finished(2), false),
+        ];
+
+        let changes = rate(&results, &config(0.0));
+
+        assert_eq!(changes.len(), 5);
+        assert_eq!(after(&changes, 1, 1), Some(1516.0));
+        assert_eq!(after(&changes, 1, 2), Some(1500.0));
+        assert_eq!(after(&changes, 1, 3), Some(1484.0));
+        let total: f64 = changes.iter().map(|c| c.rating_after - c.rating_before).sum();
+        assert!(total.abs() < 1e-9);
+
+        //The upset win over a higher rated driver is worth more than the first win was
+        let upset = changes.iter().find(|c| c.race_id == 2 && c.driver_id == 3).unwrap();
+        assert_eq!(upset.rating_before, 1484.0);
+        assert!(upset.rating_after - upset.rating_before > 16.0);
+    }
+
+    #[test]
+    fn bot_results_are_excluded_or_discounted() {
+        let results = vec![
+            result(1, 1, Position::Finished(1), true),
+            result(1, 2, Position::Finished(2), false),
+            result(1, 3, Position::Finished(3), false),
+        ];
+
+        let excluded = rate(&results, &config(0.0));
+        assert_eq!(after(&excluded, 1, 1), None);
+        assert_eq!(after(&excluded, 1, 2), Some(1516.0));
+        assert_eq!(after(&excluded, 1, 3), Some(1484.0));
+
+        let discounted = rate(&results, &config(0.5));
+        assert_eq!(after(&discounted, 1, 1), Some(1516.0));
+        let second = after(&discounted, 1, 2).unwrap();
+        assert!((second - (1500.0 + 32.0 / 6.0)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn replay_without_bots_carries_no_bot_deltas() {
+        let results = vec![
+            result(1, 1, Position::Finished(1), true),
+            result(1, 2, Position::Finished(2), false),
+            result(2, 2, Position::Finished(1), false),
+            result(2, 3, Position::Finished(2), false),
+        ];
+
+        //Counted in full, the loss against the bot carries over into the next race
+        let included = rate(&results, &config(1.0));
+        let next = included.iter().find(|c| c.race_id == 2 && c.driver_id == 2).unwrap();
+        assert_eq!(next.rating_before, 1484.0);
+
+        let without_bots: Vec<RatingInput> = results.into_iter().filter(|r| !r.bot_result).collect();
+        let excluded = rate(&without_bots, &config(1.0));
+        let next = excluded.iter().find(|c| c.race_id == 2 && c.driver_id == 2).unwrap();
+        assert_eq!(next.rating_before, 1500.0);
+        assert_eq!(next.rating_after, 1516.0);
+    }
+
+    #[test]
+    fn non_starters_are_left_out() {
+        let results = vec![
+            result(1, 1, Position::Finished(1), false),
+            result(1, 2, Position::Dsq, false),
+            result(1, 3, Position::Dns, false),
+            result(2, 1, Position::Finished(1), false),
+            result(2, 3, Position::Dns, false),
+        ];
+
+        let changes = rate(&results, &config(0.0));
+
+        //Only the winner and the disqualified driver are rated, as if the non starter had not entered
+        assert_eq!(changes.len(), 2);
+        assert_eq!(after(&changes, 1, 1), Some(1516.0));
+        assert_eq!(after(&changes, 1, 2), Some(1484.0));
+        assert_eq!(after(&changes, 1, 3), None);
+        assert_eq!(after(&changes, 2, 1), None);
+    }
+}
@@ -0,0 +1,287 @@
+use std::collections::BTreeSet;
+use std::future::Future;
+use std::pin::pin;
+use std::time::Duration;
+
+use futures::future::{self, Either};
+
+use sqlx::postgres::PgListener;
+use sqlx::{Pool, Postgres};
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, timeout, Instant};
+use tracing::{error, info, info_span, warn, Instrument};
+
+use crate::config::{RatingConfig, RecalcConfig};
+use crate::utils::cache::ResponseCache;
+use crate::utils::metrics::Timed;
+use crate::utils::{db, rating};
+
+//Channel the database triggers notify on, the payload is the changed season
+pub const NOTIFY_CHANNEL: &str = "season_recalc";
+//Changes to drivers, seats, teams and races that do not need a recalculation, but do outdate cached responses
+pub const DATA_CHANNEL: &str = "data_changed";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trigger {
+    Season(i32),
+    //Something may have been missed, e.g. at startup or after losing the listener connection
+    CatchUp,
+}
+
+#[derive(Debug, Clone)]
+pub struct RecalcHandle {
+    sender: mpsc::UnboundedSender<Trigger>,
+}
+
+impl RecalcHandle {
+    pub fn request(&self, trigger: Trigger) {
+        if self.sender.send(trigger).is_err() {
+            warn!(?trigger, "Recalculation worker is not running");
+        }
+    }
+
+    //The worker owns the receiving end, so the channel closes when it exits or panics
+    pub fn is_running(&self) -> bool {
+        !self.sender.is_closed()
+    }
+}
+
+//Owned by whoever started the worker, used to stop it when the server shuts down
+#[derive(Debug)]
+pub struct RecalcWorker {
+    shutdown: watch::Sender<bool>,
+    worker: JoinHandle<()>,
+    listener: JoinHandle<()>,
+}
+
+impl RecalcWorker {
+    //Lets a running recalculation finish, but nothing new is started.
+    //Past the timeout the worker is aborted, which rolls back the season it was working on
+    pub async fn shutdown(self, grace: Duration) {
+        //Signalled before the listener goes away, the worker would otherwise see its channel close first
+        let _ = self.shutdown.send(true);
+        self.listener.abort();
+
+        let mut worker = self.worker;
+        match timeout(grace, &mut worker).await {
+            Ok(_) => info!("Recalculation worker shut down"),
+            Err(_) => {
+                warn!(?grace, "Recalculation worker did not stop in time, aborting it");
+                worker.abort();
+            }
+        }
+    }
+}
+
+//Starts the recalculation worker and the database listener feeding it
+pub fn spawn(
+    pool: Pool<Postgres>,
+    config: RecalcConfig,
+    rating: RatingConfig,
+    cache: ResponseCache,
+) -> (RecalcHandle, RecalcWorker) {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    let (shutdown, stopping) = watch::channel(false);
+    let handle = RecalcHandle { sender };
+
+    let worker = tokio::spawn(run_worker(
+        pool.clone(),
+        config.clone(),
+        rating,
+        cache.clone(),
+        receiver,
+        stopping,
+    ));
+    let listener = tokio::spawn(listen(pool, config, handle.clone(), cache));
+    handle.request(Trigger::CatchUp);
+
+    (
+        handle,
+        RecalcWorker {
+            shutdown,
+            worker,
+            listener,
+        },
+    )
+}
+
+//None when shutdown was requested before the future completed
+async fn unless_stopping<F: Future>(stopping: &mut watch::Receiver<bool>, future: F) -> Option<F::Output> {
+    let stopped = stopping.wait_for(|stopping| *stopping);
+    match future::select(pin!(stopped), pin!(future)).await {
+        Either::Left(_) => None,
+        Either::Right((output, _)) => Some(output),
+    }
+}
+
+async fn run_worker(
+    pool: Pool<Postgres>,
+    config: RecalcConfig,
+    rating: RatingConfig,
+    cache: ResponseCache,
+    mut receiver: mpsc::UnboundedReceiver<Trigger>,
+    mut stopping: watch::Receiver<bool>,
+) {
+    //Flagged seasons stay flagged when stopping, the catch up at the next start picks them up
+    while let Some(Some(trigger)) = unless_stopping(&mut stopping, receiver.recv()).await {
+        let mut seasons = BTreeSet::new();
+        let mut catch_up = false;
+        let mut add = |trigger| match trigger {
+            Trigger::Season(season) => {
+                seasons.insert(season);
+            }
+            Trigger::CatchUp => catch_up = true,
+        };
+        add(trigger);
+
+        //Wait for the changes to settle so a batch of writes causes a single recalculation
+        let deadline = Instant::now() + config.max_delay;
+        loop {
+            let wait = config.debounce.min(deadline.saturating_duration_since(Instant::now()));
+            match unless_stopping(&mut stopping, timeout(wait, receiver.recv())).await {
+                Some(Ok(Some(trigger))) => add(trigger),
+                Some(Ok(None) | Err(_)) => break,
+                None => {
+                    info!("Recalculation worker stopped");
+                    return;
+                }
+            }
+        }
+
+        //Every job is a trace of its own, with the repository calls it makes below it
+        let span = info_span!("recalculation", ?seasons, catch_up);
+        let stopped = async {
+            info!(?seasons, catch_up, "Recalculating season results");
+            //A catch up has to look at every flagged season, not only the ones we heard about
+            let only = (!catch_up).then_some(seasons);
+            recalculate_with_retry(&pool, &config, only, &mut stopping).await;
+            cache.invalidate();
+
+            //The catch up at the next start rebuilds the ratings, no need to hold up the shutdown for it
+            if *stopping.borrow() {
+                return true;
+            }
+
+            //Ratings span every season, so any change replays the whole history
+            let started = Instant::now();
+            match rating::rebuild(&pool, &rating).await {
+                Ok(ratings) => info!(ratings, elapsed = ?started.elapsed(), "Driver ratings rebuilt"),
+                Err(e) => error!(error = %e, "Failed to rebuild driver ratings"),
+            }
+            cache.invalidate();
+            false
+        }
+        .instrument(span)
+        .await;
+        if stopped {
+            break;
+        }
+    }
+    info!("Recalculation worker stopped");
+}
+
+//Retries the seasons that failed, the ones that succeeded already had their flags cleared
+async fn recalculate_with_retry(
+    pool: &Pool<Postgres>,
+    config: &RecalcConfig,
+    mut only: Option<BTreeSet<i32>>,
+    stopping: &mut watch::Receiver<bool>,
+) {
+    let mut backoff = config.initial_backoff;
+    for attempt in 1..=config.max_attempts {
+        let failed = match db::update_season_results(pool, only.as_ref())
+            .timed("update_season_results")
+            .await
+        {
+            Ok(runs) => {
+                for run in &runs {
+                    match &run.error {
+                        None => info!(
+                            attempt,
+                            season = run.season,
+                            duration_ms = run.duration_ms,
+                            rows_written = run.rows_written,
+                            "Season results recalculated"
+                        ),
+                        Some(e) => warn!(attempt, season = run.season, error = %e, "Season recalculation failed"),
+                    }
+                }
+                let failed: BTreeSet<i32> = runs
+                    .iter()
+                    .filter(|run| run.error.is_some())
+                    .map(|run| run.season)
+                    .collect();
+                if failed.is_empty() {
+                    return;
+                }
+                only = Some(failed.clone());
+                format!("seasons {failed:?} failed")
+            }
+            Err(e) => e.to_string(),
+        };
+
+        if attempt == config.max_attempts {
+            error!(attempt, error = %failed, "Season recalculation failed, giving up until the next change");
+            return;
+        }
+        warn!(attempt, error = %failed, retry_in = ?backoff, "Retrying season recalculation");
+        if unless_stopping(stopping, sleep(backoff)).await.is_none() {
+            warn!(error = %failed, "Shutting down, the failed seasons are retried at the next start");
+            return;
+        }
+        backoff = (backoff * 2).min(config.max_backoff);
+    }
+}
+
+//Forwards notifications from database triggers, so edits made outside this server are picked up too
+async fn listen(pool: Pool<Postgres>, config: RecalcConfig, handle: RecalcHandle, cache: ResponseCache) {
+    let mut backoff = config.initial_backoff;
+    loop {
+        let mut listener = match connect_listener(&pool).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                error!(error = %e, retry_in = ?backoff, "Failed to listen for season changes");
+                sleep(backoff).await;
+                backoff = (backoff * 2).min(config.max_backoff);
+                continue;
+            }
+        };
+        backoff = config.initial_backoff;
+
+        loop {
+            match listener.try_recv().await {
+                Ok(Some(notification)) if notification.channel() == DATA_CHANNEL => cache.invalidate(),
+                Ok(Some(notification)) => match notification.payload().parse() {
+                    Ok(season) => {
+                        cache.invalidate();
+                        handle.request(Trigger::Season(season));
+                    }
+                    Err(_) => {
+                        warn!(payload = notification.payload(), "Unexpected season change payload");
+                        handle.request(Trigger::CatchUp);
+                    }
+                },
+                //The listener reconnects by itself, but notifications sent in between are lost
+                Ok(None) => {
+                    warn!("Lost the season change listener connection, reconnecting");
+                    cache.invalidate();
+                    handle.request(Trigger::CatchUp);
+                }
+                Err(e) => {
+                    error!(error = %e, "Season change listener failed");
+                    cache.invalidate();
+                    handle.request(Trigger::CatchUp);
+                    sleep(Duration::from_secs(1)).await;
+                    break;
+                }
+            }
+        }
+    }
+}
+
+async fn connect_listener(pool: &Pool<Postgres>) -> Result<PgListener, sqlx::Error> {
+    let mut listener = PgListener::connect_with(pool).await?;
+    listener.listen_all([NOTIFY_CHANNEL, DATA_CHANNEL]).await?;
+    Ok(listener)
+}
@@ -0,0 +1,58 @@
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::middleware::Next;
+use actix_web::{Error, HttpMessage};
+use tracing::Span;
+use tracing_actix_web::{root_span, DefaultRootSpanBuilder, RootSpanBuilder};
+
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+//Longer ids sent by clients are replaced rather than copied into every log line
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+//The id a request is logged under, taken from X-Request-Id or generated when the client did not send one
+#[derive(Debug, Clone)]
+pub struct RequestId(pub String);
+
+fn from_header(request: &ServiceRequest) -> Option<String> {
+    let value = request.headers().get(REQUEST_ID_HEADER)?.to_str().ok()?.trim();
+    let valid = !value.is_empty()
+        && value.len() <= MAX_REQUEST_ID_LENGTH
+        && value.chars().all(|c| c.is_ascii_graphic());
+    valid.then(|| value.to_string())
+}
+
+//Root span of every request, carrying the request id so every span and event below it can be correlated
+pub struct RequestIdRootSpan;
+
+impl RootSpanBuilder for RequestIdRootSpan {
+    fn on_request_start(request: &ServiceRequest) -> Span {
+        //TracingLogger has already generated its own id, used when the client did not send one
+        let request_id = from_header(request).unwrap_or_else(|| {
+            request
+                .extensions()
+                .get::<tracing_actix_web::RequestId>()
+                .map(ToString::to_string)
+                .unwrap_or_default()
+        });
+        request.extensions_mut().insert(RequestId(request_id.clone()));
+        root_span!(request, correlation_id = %request_id)
+    }
+
+    fn on_request_end<B: MessageBody>(span: Span, outcome: &Result<ServiceResponse<B>, Error>) {
+        DefaultRootSpanBuilder::on_request_end(span, outcome);
+    }
+}
+
+//Returns the request id to the client, has to run inside TracingLogger
+pub async fn echo_request_id(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let mut response = next.call(req).await?;
+    let request_id = response.request().extensions().get::<RequestId>().cloned();
+    if let Some(value) = request_id.and_then(|id| HeaderValue::from_str(&id.0).ok()) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    Ok(response)
+}
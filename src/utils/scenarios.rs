@@ -0,0 +1,293 @@
+use std::collections::HashMap;
+
+use crate::models::db_objects::{
+    BotPolicy, ClinchCondition, DriverScenario, HypotheticalResult, PointsEntry, ProgressionResult, RaceInfo,
+    SeasonScenarios, TeamScenario,
+};
+use crate::utils::standings;
+
+//Drivers a team is assumed to field when it has not raced yet
+const DEFAULT_TEAM_SEATS: usize = 2;
+
+fn bonus_mask(entry: &PointsEntry) -> usize {
+    usize::from(entry.pole) | usize::from(entry.leading_lap) << 1 | usize::from(entry.fastest_lap) << 2
+}
+
+//Points a hypothetical result would be worth, results outside the scheme score nothing
+pub fn points_for(points: &[PointsEntry], result: &HypotheticalResult) -> i32 {
+    points
+        .iter()
+        .find(|entry| {
+            entry.position == result.position.code()
+                && entry.pole == result.pole
+                && entry.leading_lap == result.leading_lap
+                && entry.fastest_lap == result.fastest_lap
+        })
+        .map_or(0, |entry| entry.points)
+}
+
+//Most points a team can take from one race with this many drivers.
+//Every driver needs a different position and each bonus can only go to one of them
+pub fn max_points_per_race(points: &[PointsEntry], seats: usize) -> i32 {
+    let mut by_position: HashMap<i32, [Option<i32>; 8]> = HashMap::new();
+    for entry in points {
+        let options = by_position.entry(entry.position).or_insert([None; 8]);
+        let slot = &mut options[bonus_mask(entry)];
+        *slot = Some(slot.map_or(entry.points, |points| points.max(entry.points)));
+    }
+
+    //best[drivers][used bonuses]
+    let mut best = vec![[None::<i32>; 8]; seats + 1];
+    best[0][0] = Some(0);
+    for options in by_position.values() {
+        let mut next = best.clone();
+        for drivers in 0..seats {
+            for used in 0..8 {
+                let Some(total) = best[drivers][used] else {
+                    continue;
+                };
+                for (mask, points) in options.iter().enumerate() {
+                    if let Some(points) = points.filter(|_| mask & used == 0) {
+                        let slot = &mut next[drivers + 1][used | mask];
+                        *slot = Some(slot.map_or(total + points, |best| best.max(total + points)));
+                    }
+                }
+            }
+        }
+        best = next;
+    }
+
+    best.iter().flatten().flatten().copied().max().unwrap_or(0)
+}
+
+//Leader against whoever can still get closest, ties on points are not counted as clinched
+fn clinch(contenders: &[(i32, i32, i32, i32)], remaining: usize) -> Option<ClinchCondition> {
+    let (leader_id, leader_points, _, _) = *contenders.first()?;
+    let rival = contenders[1..].iter().max_by_key(|(_, _, max_points, _)| *max_points);
+
+    Some(match rival {
+        Some(&(rival_id, rival_points, rival_max, per_race)) => ClinchCondition {
+            leader_id,
+            rival_id: Some(rival_id),
+            lead: leader_points - rival_points,
+            clinched: leader_points > rival_max,
+            points_needed: (rival_max - leader_points + 1).max(0),
+            lead_needed_after_next_race: (remaining > 0)
+                .then(|| (remaining as i32 - 1) * per_race + 1),
+        },
+        None => ClinchCondition {
+            leader_id,
+            rival_id: None,
+            lead: 0,
+            clinched: true,
+            points_needed: 0,
+            lead_needed_after_next_race: None,
+        },
+    })
+}
+
+pub fn build_scenarios(
+    season: i32,
+    results: Vec<ProgressionResult>,
+    remaining_races: Vec<RaceInfo>,
+    hypothetical_races: Vec<i32>,
+    points: &[PointsEntry],
+    bots: BotPolicy,
+) -> SeasonScenarios {
+    let remaining = remaining_races.len();
+
+    let mut entries: HashMap<(i32, i32), usize> = HashMap::new();
+    for result in &results {
+        *entries.entry((result.race_id, result.team.team_id)).or_default() += 1;
+    }
+    let mut team_seats: HashMap<i32, usize> = HashMap::new();
+    for ((_, team_id), count) in entries {
+        let seats = team_seats.entry(team_id).or_default();
+        *seats = (*seats).max(count);
+    }
+
+    let driver_per_race = max_points_per_race(points, 1);
+    let mut team_per_race: HashMap<usize, i32> = HashMap::new();
+
+    let progression = standings::build_progression(season, results, bots);
+    let (drivers, teams) = progression
+        .rounds
+        .last()
+        .map(|round| (round.drivers.clone(), round.teams.clone()))
+        .unwrap_or_default();
+
+    let driver_contenders: Vec<(i32, i32, i32, i32)> = drivers
+        .iter()
+        .map(|standing| {
+            let max_points = standing.total_points + remaining as i32 * driver_per_race;
+            (standing.driver_id, standing.total_points, max_points, driver_per_race)
+        })
+        .collect();
+    let team_contenders: Vec<(i32, i32, i32, i32)> = teams
+        .iter()
+        .map(|standing| {
+            let seats = team_seats
+                .get(&standing.team.team_id)
+                .copied()
+                .unwrap_or(DEFAULT_TEAM_SEATS);
+            let per_race = *team_per_race
+                .entry(seats)
+                .or_insert_with(|| max_points_per_race(points, seats));
+            let max_points = standing.total_points + remaining as i32 * per_race;
+            (standing.team.team_id, standing.total_points, max_points, per_race)
+        })
+        .collect();
+
+    let driver_clinch = clinch(&driver_contenders, remaining);
+    let team_clinch = clinch(&team_contenders, remaining);
+    let leader_points = |contenders: &[(i32, i32, i32, i32)]| {
+        contenders.first().map_or(0, |(_, points, _, _)| *points)
+    };
+    let driver_leader_points = leader_points(&driver_contenders);
+    let team_leader_points = leader_points(&team_contenders);
+
+    let drivers = drivers
+        .into_iter()
+        .zip(&driver_contenders)
+        .map(|(standing, (_, _, max_points, _))| DriverScenario {
+            position: standing.position,
+            clinched: standing.position == 1 && driver_clinch.as_ref().is_some_and(|c| c.clinched),
+            in_contention: *max_points >= driver_leader_points,
+            max_points: *max_points,
+            driver_id: standing.driver_id,
+            username: standing.username,
+            team: standing.team,
+            points: standing.total_points,
+        })
+        .collect();
+    let teams = teams
+        .into_iter()
+        .zip(&team_contenders)
+        .map(|(standing, (_, _, max_points, _))| TeamScenario {
+            position: standing.position,
+            clinched: standing.position == 1 && team_clinch.as_ref().is_some_and(|c| c.clinched),
+            in_contention: *max_points >= team_leader_points,
+            max_points: *max_points,
+            team: standing.team,
+            points: standing.total_points,
+        })
+        .collect();
+
+    SeasonScenarios {
+        season,
+        remaining_races,
+        hypothetical_races,
+        max_driver_points_per_race: driver_per_race,
+        drivers,
+        teams,
+        driver_clinch,
+        team_clinch,
+        warnings: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::db_objects::{Position, Team};
+
+    //25-18-15 with a point for pole and one for fastest lap
+    fn scheme() -> Vec<PointsEntry> {
+        let mut scheme = Vec::new();
+        for (position, points) in [(1, 25), (2, 18), (3, 15), (101, 0)] {
+            for pole in [false, true] {
+                for fastest_lap in [false, true] {
+                    scheme.push(PointsEntry {
+                        position,
+                        pole,
+                        leading_lap: false,
+                        fastest_lap,
+                        points: points + i32::from(pole) + i32::from(fastest_lap),
+                    });
+                }
+            }
+        }
+        scheme
+    }
+
+    fn result(race_id: i32, driver_id: i32, team_id: i32, points: i32) -> ProgressionResult {
+        ProgressionResult {
+            race_id,
+            race_name: format!("Race {race_id}"),
+            round: race_id,
+            driver_id,
+            username: format!("driver{driver_id}"),
+            team: Team {
+                team_id,
+                name: format!("Team {team_id}"),
+                color: None,
+            },
+            position: Position::Finished(1),
+            points,
+            bot_result: false,
+        }
+    }
+
+    fn remaining(count: i32) -> Vec<RaceInfo> {
+        (0..count)
+            .map(|round| RaceInfo {
+                race_name: format!("Race {}", round + 10),
+                season: 1,
+                race_id: round + 10,
+                round: round + 10,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn max_points_spread_bonuses_over_distinct_positions() {
+        assert_eq!(max_points_per_race(&scheme(), 1), 27);
+        assert_eq!(max_points_per_race(&scheme(), 2), 45);
+        assert_eq!(max_points_per_race(&scheme(), 3), 60);
+        assert_eq!(max_points_per_race(&[], 2), 0);
+    }
+
+    #[test]
+    fn reports_contention_and_clinch_conditions() {
+        let results = vec![
+            result(1, 1, 1, 25),
+            result(1, 2, 2, 18),
+            result(1, 3, 2, 0),
+            result(2, 1, 1, 25),
+            result(2, 2, 2, 0),
+            result(2, 3, 2, 0),
+        ];
+
+        let scenarios = build_scenarios(1, results, remaining(1), Vec::new(), &scheme(), BotPolicy::Include);
+
+        let drivers: Vec<(i32, i32, bool)> = scenarios
+            .drivers
+            .iter()
+            .map(|d| (d.driver_id, d.max_points, d.in_contention))
+            .collect();
+        assert_eq!(drivers, vec![(1, 77, true), (2, 45, false), (3, 27, false)]);
+        assert!(scenarios.drivers[0].clinched);
+        assert_eq!(
+            scenarios.driver_clinch,
+            Some(ClinchCondition {
+                leader_id: 1,
+                rival_id: Some(2),
+                lead: 32,
+                clinched: true,
+                points_needed: 0,
+                lead_needed_after_next_race: Some(1),
+            })
+        );
+
+        //Team 2 fields two drivers, so it can still take 45 points from the last race
+        let teams: Vec<(i32, i32, bool)> = scenarios
+            .teams
+            .iter()
+            .map(|t| (t.team.team_id, t.max_points, t.in_contention))
+            .collect();
+        assert_eq!(teams, vec![(1, 77, true), (2, 63, true)]);
+        let team_clinch = scenarios.team_clinch.unwrap();
+        assert!(!team_clinch.clinched);
+        assert_eq!(team_clinch.points_needed, 14);
+    }
+}
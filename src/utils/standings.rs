@@ -0,0 +1,237 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use itertools::Itertools;
+
+use crate::models::db_objects::{
+    BotPolicy, BotShare, DriverStanding, ProgressionResult, RoundStandings, SeasonProgression, Team,
+    TeamStanding,
+};
+
+#[derive(Debug, Clone)]
+struct Tally {
+    points: i32,
+    total_points: i32,
+    //Finishing positions so far, best first, used to break ties on points
+    finishes: Vec<i32>,
+    bots: BotShare,
+}
+
+impl Tally {
+    fn new() -> Self {
+        Tally {
+            points: 0,
+            total_points: 0,
+            finishes: Vec::new(),
+            bots: BotShare::default(),
+        }
+    }
+
+    fn add(&mut self, result: &ProgressionResult) {
+        self.points += result.points;
+        self.total_points += result.points;
+        if result.bot_result {
+            self.bots.results += 1;
+            self.bots.points += result.points;
+        }
+        if result.position.is_finished() {
+            let position = result.position.code();
+            let index = self.finishes.partition_point(|finish| *finish <= position);
+            self.finishes.insert(index, position);
+        }
+    }
+
+    //More points first, then countback: most wins, then most second places and so on
+    fn rank(&self, other: &Self) -> Ordering {
+        other
+            .total_points
+            .cmp(&self.total_points)
+            .then_with(|| countback(&self.finishes, &other.finishes))
+    }
+}
+
+fn countback(finishes: &[i32], other: &[i32]) -> Ordering {
+    for index in 0..finishes.len().max(other.len()) {
+        match (finishes.get(index), other.get(index)) {
+            (Some(a), Some(b)) if a != b => return a.cmp(b),
+            (Some(_), None) => return Ordering::Less,
+            (None, Some(_)) => return Ordering::Greater,
+            _ => {}
+        }
+    }
+    Ordering::Equal
+}
+
+//Cumulative driver and team standings after every round, in calendar order
+pub fn build_progression(
+    season: i32,
+    mut results: Vec<ProgressionResult>,
+    bots: BotPolicy,
+) -> SeasonProgression {
+    results.retain(|result| bots.keeps(result.bot_result));
+    results.sort_by_key(|result| (result.round, result.race_id));
+
+    let mut drivers: HashMap<i32, (String, Team, Tally)> = HashMap::new();
+    let mut teams: HashMap<i32, (Team, Tally)> = HashMap::new();
+    let mut rounds = Vec::new();
+
+    for (race_id, race) in &results.iter().chunk_by(|result| result.race_id) {
+        let race: Vec<&ProgressionResult> = race.collect();
+
+        drivers.values_mut().for_each(|(_, _, tally)| tally.points = 0);
+        teams.values_mut().for_each(|(_, tally)| tally.points = 0);
+        for result in &race {
+            let (username, team, tally) = drivers
+                .entry(result.driver_id)
+                .or_insert_with(|| (result.username.clone(), result.team.clone(), Tally::new()));
+            username.clone_from(&result.username);
+            team.clone_from(&result.team);
+            tally.add(result);
+
+            teams
+                .entry(result.team.team_id)
+                .or_insert_with(|| (result.team.clone(), Tally::new()))
+                .1
+                .add(result);
+        }
+
+        let driver_standings = drivers
+            .iter()
+            .sorted_by(|(a_id, (_, _, a)), (b_id, (_, _, b))| a.rank(b).then(a_id.cmp(b_id)))
+            .enumerate()
+            .map(|(index, (driver_id, (username, team, tally)))| DriverStanding {
+                position: index as i32 + 1,
+                driver_id: *driver_id,
+                username: username.clone(),
+                team: team.clone(),
+                points: tally.points,
+                total_points: tally.total_points,
+                bots: bots.flags().then_some(tally.bots),
+            })
+            .collect();
+        let team_standings = teams
+            .iter()
+            .sorted_by(|(a_id, (_, a)), (b_id, (_, b))| a.rank(b).then(a_id.cmp(b_id)))
+            .enumerate()
+            .map(|(index, (_, (team, tally)))| TeamStanding {
+                position: index as i32 + 1,
+                team: team.clone(),
+                points: tally.points,
+                total_points: tally.total_points,
+                bots: bots.flags().then_some(tally.bots),
+            })
+            .collect();
+
+        rounds.push(RoundStandings {
+            race_id,
+            race_name: race[0].race_name.clone(),
+            round: race[0].round,
+            drivers: driver_standings,
+            teams: team_standings,
+        });
+    }
+
+    SeasonProgression { season, rounds }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::db_objects::Position;
+
+    fn result(round: i32, driver_id: i32, team_id: i32, position: Position, points: i32) -> ProgressionResult {
+        ProgressionResult {
+            race_id: round + 10,
+            race_name: format!("Round {round}"),
+            round,
+            driver_id,
+            username: format!("driver{driver_id}"),
+            team: Team {
+                team_id,
+                name: format!("Team {team_id}"),
+                color: None,
+            },
+            position,
+            points,
+            bot_result: false,
+        }
+    }
+
+    fn order(round: &RoundStandings) -> Vec<(i32, i32)> {
+        round
+            .drivers
+            .iter()
+            .map(|standing| (standing.driver_id, standing.total_points))
+            .collect()
+    }
+
+    #[test]
+    fn accumulates_points_per_round() {
+        let results = vec![
+            result(2, 2, 1, Position::Finished(1), 25),
+            result(1, 1, 1, Position::Finished(1), 25),
+            result(1, 2, 1, Position::Finished(2), 18),
+            result(1, 3, 2, Position::Finished(3), 15),
+            result(2, 1, 1, Position::Dnf, 0),
+            result(2, 3, 2, Position::Finished(2), 18),
+        ];
+
+        let progression = build_progression(4, results, BotPolicy::Include);
+
+        assert_eq!(progression.season, 4);
+        assert_eq!(progression.rounds.len(), 2);
+        assert_eq!(order(&progression.rounds[0]), vec![(1, 25), (2, 18), (3, 15)]);
+        assert_eq!(order(&progression.rounds[1]), vec![(2, 43), (3, 33), (1, 25)]);
+        assert_eq!(progression.rounds[1].drivers[0].points, 25);
+        assert_eq!(progression.rounds[1].drivers[2].points, 0);
+
+        let teams: Vec<(i32, i32, i32)> = progression.rounds[1]
+            .teams
+            .iter()
+            .map(|standing| (standing.team.team_id, standing.points, standing.total_points))
+            .collect();
+        assert_eq!(teams, vec![(1, 25, 68), (2, 18, 33)]);
+    }
+
+    #[test]
+    fn breaks_ties_on_countback() {
+        let results = vec![
+            result(1, 1, 1, Position::Finished(2), 18),
+            result(1, 2, 2, Position::Finished(1), 25),
+            result(2, 1, 1, Position::Finished(2), 18),
+            result(2, 2, 2, Position::Finished(5), 11),
+            result(2, 3, 3, Position::Finished(1), 25),
+        ];
+
+        let progression = build_progression(4, results, BotPolicy::Include);
+
+        //Level on points, driver 2 is ahead on their win
+        assert_eq!(order(&progression.rounds[1]), vec![(2, 36), (1, 36), (3, 25)]);
+        assert_eq!(progression.rounds[1].drivers[1].position, 2);
+    }
+
+    #[test]
+    fn applies_bot_policy() {
+        let mut bot = result(2, 2, 2, Position::Finished(1), 25);
+        bot.bot_result = true;
+        let results = vec![
+            result(1, 1, 1, Position::Finished(1), 25),
+            result(1, 2, 2, Position::Finished(2), 18),
+            bot,
+            result(2, 1, 1, Position::Finished(2), 18),
+        ];
+
+        let excluded = build_progression(4, results.clone(), BotPolicy::Exclude);
+        assert_eq!(order(&excluded.rounds[1]), vec![(1, 43), (2, 18)]);
+        assert_eq!(excluded.rounds[1].drivers[0].bots, None);
+
+        let flagged = build_progression(4, results, BotPolicy::Flag);
+        assert_eq!(order(&flagged.rounds[1]), vec![(1, 43), (2, 43)]);
+        assert_eq!(flagged.rounds[1].drivers[0].bots, Some(BotShare::default()));
+        assert_eq!(
+            flagged.rounds[1].drivers[1].bots,
+            Some(BotShare { results: 1, points: 25 })
+        );
+        assert_eq!(flagged.rounds[1].teams[1].bots.map(|bots| bots.points), Some(25));
+    }
+}
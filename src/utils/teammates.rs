@@ -0,0 +1,184 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+
+use itertools::Itertools;
+
+use crate::models::db_objects::{BotPolicy, HeadToHeadScore, TeammateBattle, TeammateResult};
+
+fn score(score: &mut HeadToHeadScore, ordering: Ordering) {
+    match ordering {
+        Ordering::Less => score.driver += 1,
+        Ordering::Greater => score.teammate += 1,
+        Ordering::Equal => {}
+    }
+}
+
+fn battle(
+    season: i32,
+    team_id: i32,
+    driver: &[&TeammateResult],
+    teammate: &[&TeammateResult],
+    bots: BotPolicy,
+) -> Option<TeammateBattle> {
+    let teammate_races: HashMap<i32, &TeammateResult> =
+        teammate.iter().map(|result| (result.race_id, *result)).collect();
+    let shared: Vec<(&TeammateResult, &TeammateResult)> = driver
+        .iter()
+        .filter_map(|result| Some((*result, *teammate_races.get(&result.race_id)?)))
+        .collect();
+    let (first, other) = *shared.first()?;
+
+    let mut qualifying = HeadToHeadScore::default();
+    let mut race = HeadToHeadScore::default();
+    let mut gaps = Vec::new();
+    for (a, b) in &shared {
+        if let (Some(a), Some(b)) = (a.qualifying, b.qualifying) {
+            score(&mut qualifying, a.cmp(&b));
+        }
+        if a.position.is_finished() && b.position.is_finished() {
+            score(&mut race, a.position.cmp(&b.position));
+            gaps.push(f64::from(b.position.code() - a.position.code()));
+        }
+    }
+
+    let driver_points: i32 = shared.iter().map(|(a, _)| a.points).sum();
+    let teammate_points: i32 = shared.iter().map(|(_, b)| b.points).sum();
+    let total = driver_points + teammate_points;
+
+    Some(TeammateBattle {
+        season,
+        team_id,
+        driver_id: first.driver_id,
+        driver_username: first.username.clone(),
+        teammate_id: other.driver_id,
+        teammate_username: other.username.clone(),
+        races: shared.len() as i32,
+        qualifying,
+        race,
+        driver_points,
+        teammate_points,
+        driver_points_share: (total > 0).then(|| f64::from(driver_points) / f64::from(total)),
+        average_position_gap: (!gaps.is_empty()).then(|| gaps.iter().sum::<f64>() / gaps.len() as f64),
+        bot_races: bots.flags().then(|| {
+            shared
+                .iter()
+                .filter(|(a, b)| a.bot_result || b.bot_result)
+                .count() as i32
+        }),
+    })
+}
+
+//Every pair of drivers that shared a team in a season, the lower driver id first
+pub fn teammate_battles(results: &[TeammateResult], bots: BotPolicy) -> Vec<TeammateBattle> {
+    let mut line_ups: BTreeMap<(i32, i32), BTreeMap<i32, Vec<&TeammateResult>>> = BTreeMap::new();
+    for result in results.iter().filter(|result| bots.keeps(result.bot_result)) {
+        line_ups
+            .entry((result.season, result.team_id))
+            .or_default()
+            .entry(result.driver_id)
+            .or_default()
+            .push(result);
+    }
+
+    line_ups
+        .into_iter()
+        .flat_map(|((season, team_id), drivers)| {
+            drivers
+                .values()
+                .tuple_combinations()
+                .filter_map(|(driver, teammate)| battle(season, team_id, driver, teammate, bots))
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+//The battles a driver was part of, from their side
+pub fn battles_of(battles: Vec<TeammateBattle>, driver_id: i32) -> Vec<TeammateBattle> {
+    battles
+        .into_iter()
+        .filter_map(|battle| {
+            if battle.driver_id == driver_id {
+                Some(battle)
+            } else if battle.teammate_id == driver_id {
+                Some(TeammateBattle {
+                    driver_id: battle.teammate_id,
+                    driver_username: battle.teammate_username,
+                    teammate_id: battle.driver_id,
+                    teammate_username: battle.driver_username,
+                    qualifying: HeadToHeadScore {
+                        driver: battle.qualifying.teammate,
+                        teammate: battle.qualifying.driver,
+                    },
+                    race: HeadToHeadScore {
+                        driver: battle.race.teammate,
+                        teammate: battle.race.driver,
+                    },
+                    driver_points: battle.teammate_points,
+                    teammate_points: battle.driver_points,
+                    driver_points_share: battle.driver_points_share.map(|share| 1.0 - share),
+                    average_position_gap: battle.average_position_gap.map(|gap| -gap),
+                    ..battle
+                })
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::db_objects::Position;
+
+    fn result(
+        race_id: i32,
+        driver_id: i32,
+        position: Position,
+        qualifying: i32,
+        points: i32,
+    ) -> TeammateResult {
+        TeammateResult {
+            season: 2,
+            team_id: 7,
+            race_id,
+            driver_id,
+            username: format!("driver{driver_id}"),
+            position,
+            qualifying: Some(qualifying),
+            points,
+            bot_result: false,
+        }
+    }
+
+    #[test]
+    fn compares_teammates_over_shared_races() {
+        let results = vec![
+            result(1, 1, Position::Finished(2), 1, 18),
+            result(1, 2, Position::Finished(5), 4, 10),
+            result(2, 1, Position::Dnf, 3, 0),
+            result(2, 2, Position::Finished(4), 2, 12),
+            result(3, 1, Position::Finished(1), 1, 25),
+            result(3, 2, Position::Finished(2), 2, 18),
+            //Driver 3 only drove a race the other two missed
+            result(4, 3, Position::Finished(3), 3, 15),
+        ];
+
+        let battles = teammate_battles(&results, BotPolicy::Include);
+
+        assert_eq!(battles.len(), 1);
+        let battle = &battles[0];
+        assert_eq!((battle.driver_id, battle.teammate_id, battle.races), (1, 2, 3));
+        assert_eq!(battle.qualifying, HeadToHeadScore { driver: 2, teammate: 1 });
+        assert_eq!(battle.race, HeadToHeadScore { driver: 2, teammate: 0 });
+        assert_eq!((battle.driver_points, battle.teammate_points), (43, 40));
+        assert_eq!(battle.average_position_gap, Some(2.0));
+
+        let flipped = battles_of(battles.clone(), 2);
+        assert_eq!(flipped[0].driver_id, 2);
+        assert_eq!(flipped[0].qualifying, HeadToHeadScore { driver: 1, teammate: 2 });
+        assert_eq!(flipped[0].average_position_gap, Some(-2.0));
+        assert!((flipped[0].driver_points_share.unwrap() - 40.0 / 83.0).abs() < 1e-9);
+        assert!(battles_of(battles, 3).is_empty());
+    }
+}
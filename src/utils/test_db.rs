@@ -0,0 +1,36 @@
+use sqlx::postgres::PgPoolOptions;
+use sqlx::{Pool, Postgres};
+use tokio::sync::{Mutex, MutexGuard};
+
+const SEED: &str = include_str!("../../benches/driver_info_seed.sql");
+
+//Tests share the one database, they take turns
+static LOCK: Mutex<()> = Mutex::const_new(());
+
+//For the tests that need a database: TEST_DATABASE_URL points at a database with the schema and migrations applied.
+//It is emptied and loaded with driver_info_seed.sql for every test, so never point it at data you want to keep.
+//Those tests are ignored by default, run them with: cargo test -- --ignored
+pub async fn seeded_pool() -> (MutexGuard<'static, ()>, Pool<Postgres>) {
+    let guard = LOCK.lock().await;
+    let url = std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL is not set");
+    let pool = PgPoolOptions::new().max_connections(5).connect(&url).await.unwrap();
+    empty(&pool).await;
+    sqlx::raw_sql(SEED).execute(&pool).await.unwrap();
+    (guard, pool)
+}
+
+//Removes every row but the applied migrations
+pub async fn empty(pool: &Pool<Postgres>) {
+    sqlx::raw_sql(
+        "DO $$ BEGIN
+            EXECUTE (
+                SELECT 'TRUNCATE ' || string_agg(format('%I', tablename), ', ') || ' RESTART IDENTITY CASCADE'
+                FROM pg_tables
+                WHERE schemaname = 'public' AND tablename <> '_sqlx_migrations'
+            );
+        END $$;",
+    )
+    .execute(pool)
+    .await
+    .unwrap();
+}